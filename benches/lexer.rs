@@ -0,0 +1,77 @@
+#[macro_use]
+extern crate criterion;
+extern crate ha_2_lexer;
+
+use criterion::Criterion;
+use ha_2_lexer::lang::{bnf, golang};
+
+const BNF_SAMPLE: &str = r#"
+    <A> ::= <B> | "c" <D> ;
+"#;
+
+/// Lex the same sample over and over again with a lexer which is built only once.
+fn bnf_sample(c: &mut Criterion) {
+    let lexer = bnf::make_lexer();
+
+    c.bench_function("bnf sample x 100k", |b| {
+        b.iter(|| {
+            for _ in 0..100_000 {
+                criterion::black_box(lexer.tokens(BNF_SAMPLE, "bench.bnf".into()).count());
+            }
+        })
+    });
+}
+
+const GO_SAMPLE: &str = r#"
+package main
+
+import (
+    "fmt"
+    "math"
+)
+
+// Sqrt approximates square root of x with Newton's method.
+func Sqrt(x float64) (z float64, err error) {
+    if x < 0 {
+        return 0, fmt.Errorf("negative number: %v", x)
+    }
+    z = 1.0
+    for i := 0; i < 10 && math.Abs(z*z-x) > 1e-9; i++ {
+        z -= (z*z - x) / (2 * z)
+    }
+    return
+}
+
+func main() {
+    for _, x := range []float64{2, 0x10, 1.5e3} {
+        fmt.Println(Sqrt(x))
+    }
+    var flags uint8 = 0xFF &^ 1 << 2
+    ch := make(chan rune, 'a')
+    go func() { ch <- '\n' }()
+    _ = `raw string`
+}
+"#;
+
+/// Lex a large Go source file with all rules combined versus trying rules one by one.
+fn go_combined_vs_per_rule(c: &mut Criterion) {
+    let source = GO_SAMPLE.repeat(200);
+    let combined = golang::make_lexer();
+    let per_rule = golang::lexer_builder().combined(false).build().unwrap();
+
+    let mut group = c.benchmark_group("go source");
+    group.bench_function("combined", |b| {
+        b.iter(|| combined.tokens(&source, "bench.go".into()).count())
+    });
+    group.bench_function("per rule", |b| {
+        b.iter(|| per_rule.tokens(&source, "bench.go".into()).count())
+    });
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = bnf_sample, go_combined_vs_per_rule
+}
+criterion_main!(benches);
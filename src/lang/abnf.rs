@@ -0,0 +1,236 @@
+//! # ABNF lexer
+//!
+//! ABNF (or Augmented Backus-Naur Form, [RFC 5234]) language consists of the following lexemes:
+//! - rule names (e.g.: `CRLF`, `rule-name`);
+//! - 3 operators, namely: 'definition' (`=`), 'incremental alternative' (`=/`)
+//!   and 'alternative' (`/`);
+//! - quoted strings (e.g.: `"abc"`), case-insensitive as per RFC;
+//! - numeric values in binary, decimal or hexadecimal base, either concatenated
+//!   (e.g.: `%d13.10`, `%b01`) or as a range (e.g.: `%x30-39`);
+//! - repetitions (e.g.: `*`, `1*`, `2*4`, `3`);
+//! - options (`[`, `]`);
+//! - grouping parenthesis (`(`, `)`);
+//! - prose values in angle brackets (e.g.: `<some prose>`);
+//! - comment: everything after `;` until the end of line.
+//!
+//! Unlike RFC, whitespaces (including newlines) are insignificant, so rules do not have to be
+//! indented on continuation lines.
+//!
+//! [RFC 5234]: https://tools.ietf.org/html/rfc5234
+pub use self::{AbnfOperator::*, AbnfToken::*, Base::*, Side::*};
+use lex::{ascii_whitespace_filter, Lexer, LexerBuilder, Token};
+use std::fmt;
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub enum AbnfToken<'a> {
+    RuleName(&'a str),
+    Operator(AbnfOperator),
+    /// Quoted string without quotes.
+    Quoted(&'a str),
+    /// Dot-separated concatenation of values, e.g. `%d13.10` is `Numeric(Decimal, "13.10")`.
+    Numeric(Base, &'a str),
+    /// Inclusive range of values, e.g. `%x30-39` is `Range(Hexadecimal, "30", "39")`.
+    Range(Base, &'a str, &'a str),
+    /// Repetition prefix as written in the source, e.g. `*`, `1*`, `2*4` or `3`.
+    Repeat(&'a str),
+    Optional(Side),
+    Group(Side),
+    /// Prose value without angle brackets.
+    Prose(&'a str),
+    Comment(&'a str),
+}
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub enum AbnfOperator {
+    /// Definition `"="`
+    Def,
+    /// Incremental alternative `"=/"`
+    IncAlt,
+    /// Alternative `"/"`
+    Alt,
+}
+
+/// Base of numeric values.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub enum Base {
+    /// `%b`
+    Binary,
+    /// `%d`
+    Decimal,
+    /// `%x`
+    Hexadecimal,
+}
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub enum Side {
+    Start,
+    End,
+}
+
+pub fn make_lexer<'a>() -> Lexer<'a, AbnfToken<'a>> {
+    LexerBuilder::new()
+        .skip_whitespaces(ascii_whitespace_filter)
+        .add(r";([^\n]*)\n?", |c| Comment(c.get(1).unwrap().as_str()))
+        .add(r"=/", constant!(Operator(IncAlt)))
+        .add(r"=", constant!(Operator(Def)))
+        .add(r"/", constant!(Operator(Alt)))
+        // ranges go before concatenations, otherwise "%x30-39" would be cut at "%x30"
+        .add(r"%[bB]([01]+)-([01]+)", |c| {
+            Range(Binary, c.get(1).unwrap().as_str(), c.get(2).unwrap().as_str())
+        }).add(r"%[dD]([0-9]+)-([0-9]+)", |c| {
+            Range(Decimal, c.get(1).unwrap().as_str(), c.get(2).unwrap().as_str())
+        }).add(r"%[xX]([[:xdigit:]]+)-([[:xdigit:]]+)", |c| {
+            Range(Hexadecimal, c.get(1).unwrap().as_str(), c.get(2).unwrap().as_str())
+        }).add(r"%[bB]([01]+(\.[01]+)*)", |c| {
+            Numeric(Binary, c.get(1).unwrap().as_str())
+        }).add(r"%[dD]([0-9]+(\.[0-9]+)*)", |c| {
+            Numeric(Decimal, c.get(1).unwrap().as_str())
+        }).add(r"%[xX]([[:xdigit:]]+(\.[[:xdigit:]]+)*)", |c| {
+            Numeric(Hexadecimal, c.get(1).unwrap().as_str())
+        })
+        .add(r"[0-9]*\*[0-9]*", |c| Repeat(c.get(0).unwrap().as_str()))
+        .add(r"[0-9]+", |c| Repeat(c.get(0).unwrap().as_str()))
+        .add(r"[A-Za-z][A-Za-z0-9-]*", |c| RuleName(c.get(0).unwrap().as_str()))
+        .add("\"([^\"]*)\"", |c| Quoted(c.get(1).unwrap().as_str()))
+        .add(r"<([^>]*)>", |c| Prose(c.get(1).unwrap().as_str()))
+        .add(r"\[", constant!(Optional(Start)))
+        .add(r"\]", constant!(Optional(End)))
+        .add(r"\(", constant!(Group(Start)))
+        .add(r"\)", constant!(Group(End)))
+        .build()
+        .unwrap()
+}
+
+impl Base {
+    /// Letter which follows `%` sign.
+    pub fn letter(&self) -> char {
+        match *self {
+            Binary => 'b',
+            Decimal => 'd',
+            Hexadecimal => 'x',
+        }
+    }
+}
+
+impl<'a> fmt::Display for AbnfToken<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RuleName(name) => f.write_str(name),
+            Quoted(s) => write!(f, "\"{}\"", s),
+            Numeric(base, value) => write!(f, "%{}{}", base.letter(), value),
+            Range(base, from, to) => write!(f, "%{}{}-{}", base.letter(), from, to),
+            Repeat(repeat) => f.write_str(repeat),
+            Prose(prose) => write!(f, "<{}>", prose),
+            Comment(c) => writeln!(f, ";{}", c),
+            _ => f.write_str(match *self {
+                Operator(Def) => "=",
+                Operator(IncAlt) => "=/",
+                Operator(Alt) => "/",
+                Optional(Start) => "[",
+                Optional(End) => "]",
+                Group(Start) => "(",
+                Group(End) => ")",
+                _ => unreachable!(),
+            }),
+        }
+    }
+}
+
+impl<'a> Token<'a> for AbnfToken<'a> {
+    fn describe(&self) -> String {
+        self.to_string()
+    }
+
+    fn describe_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        write!(w, "{}", self)
+    }
+
+    fn descriptor(&self) -> &'static str {
+        match *self {
+            RuleName(..) => "RuleName",
+            Quoted(..) => "Quoted",
+            Numeric(..) => "Numeric",
+            Range(..) => "Range",
+            Repeat(..) => "Repeat",
+            Prose(..) => "Prose",
+            Comment(..) => "Comment",
+            Operator(Def) => "=",
+            Operator(IncAlt) => "=/",
+            Operator(Alt) => "/",
+            Optional(Start) => "[",
+            Optional(End) => "]",
+            Group(Start) => "(",
+            Group(End) => ")",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lex::TokensExt;
+
+    const FILENAME: &str = "test.abnf";
+
+    #[test]
+    fn test_crlf() {
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens("CRLF = %d13.10", FILENAME.into())
+            .into_raw()
+            .collect();
+
+        assert_eq!(
+            tokens,
+            [
+                RuleName("CRLF"),
+                Operator(Def),
+                Numeric(Decimal, "13.10"),
+            ]
+        );
+    }
+
+    const SOURCE: &str = r#"
+        DIGIT     =  %x30-39 ; 0-9
+        rule-list =  1*( rule / (*c-wsp c-nl) )
+        rule-list =/ [ "abc" ] 2*4BIT 3<prose value> %b01
+    "#;
+
+    const TOKENS: &[AbnfToken] = &[
+        RuleName("DIGIT"),
+        Operator(Def),
+        Range(Hexadecimal, "30", "39"),
+        Comment(" 0-9"),
+        RuleName("rule-list"),
+        Operator(Def),
+        Repeat("1*"),
+        Group(Start),
+        RuleName("rule"),
+        Operator(Alt),
+        Group(Start),
+        Repeat("*"),
+        RuleName("c-wsp"),
+        RuleName("c-nl"),
+        Group(End),
+        Group(End),
+        RuleName("rule-list"),
+        Operator(IncAlt),
+        Optional(Start),
+        Quoted("abc"),
+        Optional(End),
+        Repeat("2*4"),
+        RuleName("BIT"),
+        Repeat("3"),
+        Prose("prose value"),
+        Numeric(Binary, "01"),
+    ];
+
+    #[test]
+    fn test_lexer() {
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(SOURCE, FILENAME.into())
+            .into_raw()
+            .collect();
+
+        assert_eq!(tokens, TOKENS);
+    }
+}
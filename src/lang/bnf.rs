@@ -1,22 +1,41 @@
 //! # BNF lexer
 //!
 //! This variant of BNF language consists of the following lexemes:
-//! - terminals in double quotes (e.g.: `"fn"`, `">="`);
+//! - terminals in double quotes (e.g.: `"fn"`, `">="`, `"\""`), where a backslash escapes
+//!   a double quote or another backslash; dialects which quote terminals differently, e.g.
+//!   with single quotes, are supported by `make_lexer_with_quotes`; terminals in tripled quotes
+//!   (e.g.: `"""first line` ... `last line"""`) may span multiple lines;
 //! - non-terminals in triangle quotes (e.g.: `<Condition>`, `<Rule>`);
 //! - 2 operators: 'definition' (`::=`) and 'alternative' (`|`);
 //! - rules delimiter: a semicolon (`;`).
 //!
-//! Delimiter is optional after the last rule.
-use lex::{Lexer, LexerBuilder, Token};
+//! Delimiter is optional after the last rule. Dialects which terminate rules with newlines
+//! instead are supported by `newline_delimiters`.
+use lang::{terminal_regex, unescape};
+#[cfg(feature = "std")]
+use lex::LexerFamily;
+use lex::{
+    ascii_whitespace_filter, Lexer, LexerBuilder, Location, MetaResult, Span, Token, TokenMeta,
+};
+use std::borrow::Cow;
+use std::fmt;
+use std::prelude::v1::*;
 
+/// Serialized tokens deserialize back only as long as their text needs no unescaping in the
+/// serialized form, e.g. terminals with escapes do not; see `OwnedBnfToken` for those.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub enum BnfToken<'a> {
+    /// Terminal as written between its quotes, escapes included; see [`unescape`].
+    ///
+    /// [`unescape`]: ../fn.unescape.html
     Terminal(&'a str),
     NonTerminal(&'a str),
     Operator(BnfOperator),
     Delimiter,
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub enum BnfOperator {
     /// Definition `"::="`
@@ -25,44 +44,259 @@ pub enum BnfOperator {
     Alt,
 }
 
-fn is_whitespace(c: char) -> bool {
-    let c = c as u8;
-    return c == 0x20 // spaces (U+0020)
-        || c == 0x09 // horizontal tabs (U+0009)
-        || c == 0x0d // carriage returns (U+000D)
-        || c == 0x0a; // newlines (U+000A)
+pub fn make_lexer<'a>() -> Lexer<'a, BnfToken<'a>> {
+    lexer_builder().build().unwrap()
 }
 
-fn whitespace_filter(source: &str) -> &str {
-    for (i, c) in source.char_indices() {
-        if !is_whitespace(c) {
-            return &source[i..];
-        }
-    }
-    &source[source.len()..]
+/// Same as `make_lexer`, but terminals are quoted with `quote` rather than double quotes,
+/// e.g. `'x'` for a single quote.
+pub fn make_lexer_with_quotes<'a>(quote: char) -> Lexer<'a, BnfToken<'a>> {
+    lexer_builder_with_quotes(quote).build().unwrap()
 }
 
-pub fn make_lexer<'a>() -> Lexer<'a, BnfToken<'a>> {
+/// Rules of `make_lexer`, for further tuning of the lexer.
+pub fn lexer_builder<'a>() -> LexerBuilder<'a, BnfToken<'a>> {
+    lexer_builder_with_quotes('"')
+}
+
+/// Rules of `make_lexer_with_quotes`.
+pub fn lexer_builder_with_quotes<'a>(quote: char) -> LexerBuilder<'a, BnfToken<'a>> {
+    let terminal = terminal_regex(quote);
+    // tripled quotes enclose terminals which may span multiple lines, e.g. `"""a\nb"""`
+    let multiline = format!(r"(?s){q}{q}{q}(.*?){q}{q}{q}", q = regex::escape(&quote.to_string()));
+
     LexerBuilder::new()
-        .skip_whitespaces(whitespace_filter)
+        .skip_whitespaces(ascii_whitespace_filter)
         .add(r";", constant!(BnfToken::Delimiter))
         .add(r"::=", constant!(BnfToken::Operator(BnfOperator::Def)))
         .add(r"\|", constant!(BnfToken::Operator(BnfOperator::Alt)))
         .add(r"<(.+?)>", |c| {
             BnfToken::NonTerminal(c.get(1).unwrap().as_str())
-        }).add("\"(.*?)\"", |c| {
+        }).add(&multiline, |c| {
+            BnfToken::Terminal(c.get(1).unwrap().as_str())
+        }).add(&terminal, |c| {
             BnfToken::Terminal(c.get(1).unwrap().as_str())
-        }).build()
+        })
 }
 
-impl<'a> Token<'a> for BnfToken<'a> {
+/// Lexer of `lexer_builder` for sources of any lifetime, e.g. for `tokens_from_reader`.
+///
+/// Rules are tuned by the function, e.g. `BnfLexer(|builder| builder.error_recovery(true))`.
+#[cfg(feature = "std")]
+pub struct BnfLexer(
+    pub for<'a> fn(LexerBuilder<'a, BnfToken<'a>>) -> LexerBuilder<'a, BnfToken<'a>>,
+);
+
+#[cfg(feature = "std")]
+impl Default for BnfLexer {
+    fn default() -> Self {
+        BnfLexer(|builder| builder)
+    }
+}
+
+#[cfg(feature = "std")]
+impl LexerFamily for BnfLexer {
+    type Token<'a> = BnfToken<'a>;
+
+    fn lexer<'a>(&self) -> Lexer<'a, BnfToken<'a>> {
+        (self.0)(lexer_builder()).build().unwrap()
+    }
+}
+
+impl<'a> BnfToken<'a> {
+    /// Value of a terminal with escapes resolved (see [`unescape`]), or text of any other token
+    /// as written in the source.
+    ///
+    /// Borrows whenever there is nothing to unescape.
+    ///
+    /// [`unescape`]: ../fn.unescape.html
+    pub fn unescaped(&self) -> Cow<'a, str> {
+        match *self {
+            BnfToken::Terminal(t) => unescape(t),
+            BnfToken::NonTerminal(t) => Cow::Borrowed(t),
+            _ => Cow::Borrowed(self.descriptor()),
+        }
+    }
+
+    /// Name of a non-terminal, e.g. to `filter_map` non-terminals out of tokens.
+    pub fn as_nonterminal(&self) -> Option<&'a str> {
+        match *self {
+            BnfToken::NonTerminal(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Terminal as written between its quotes, escapes included; see `unescaped`.
+    pub fn as_terminal(&self) -> Option<&'a str> {
+        match *self {
+            BnfToken::Terminal(t) => Some(t),
+            _ => None,
+        }
+    }
+}
+
+/// Same as `BnfToken`, but owns its text instead of borrowing it from the source.
+///
+/// Made by `BnfToken::into_owned`, e.g. to keep tokens after the source is dropped, or to
+/// deserialize tokens with escapes.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub enum OwnedBnfToken {
+    Terminal(String),
+    NonTerminal(String),
+    Operator(BnfOperator),
+    Delimiter,
+}
+
+impl<'a> BnfToken<'a> {
+    /// Copy borrowed text into an owned token.
+    pub fn into_owned(self) -> OwnedBnfToken {
+        match self {
+            BnfToken::Terminal(t) => OwnedBnfToken::Terminal(t.to_owned()),
+            BnfToken::NonTerminal(t) => OwnedBnfToken::NonTerminal(t.to_owned()),
+            BnfToken::Operator(op) => OwnedBnfToken::Operator(op),
+            BnfToken::Delimiter => OwnedBnfToken::Delimiter,
+        }
+    }
+}
+
+impl OwnedBnfToken {
+    /// Borrow text back as a regular token.
+    pub fn as_token(&self) -> BnfToken<'_> {
+        match *self {
+            OwnedBnfToken::Terminal(ref t) => BnfToken::Terminal(t),
+            OwnedBnfToken::NonTerminal(ref t) => BnfToken::NonTerminal(t),
+            OwnedBnfToken::Operator(op) => BnfToken::Operator(op),
+            OwnedBnfToken::Delimiter => BnfToken::Delimiter,
+        }
+    }
+}
+
+impl fmt::Display for OwnedBnfToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_token().fmt(f)
+    }
+}
+
+impl<'a> Token<'a> for OwnedBnfToken {
     fn describe(&self) -> String {
+        self.to_string()
+    }
+
+    fn describe_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        write!(w, "{}", self)
+    }
+
+    fn descriptor(&self) -> &'static str {
+        self.as_token().descriptor()
+    }
+}
+
+impl<'a> fmt::Display for BnfToken<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_with_quote(f, '"')
+    }
+}
+
+impl<'a> BnfToken<'a> {
+    /// Same as `describe`, but a terminal is quoted with `quote` as for `make_lexer_with_quotes`,
+    /// e.g. `'x'` for a single quote.
+    pub fn describe_with_quote(&self, quote: char) -> String {
+        let mut described = String::new();
+        self.fmt_with_quote(&mut described, quote).unwrap();
+        described
+    }
+
+    fn fmt_with_quote<W: fmt::Write>(&self, w: &mut W, quote: char) -> fmt::Result {
         match self {
-            BnfToken::Terminal(s) => format!("\"{}\"", s),
-            BnfToken::NonTerminal(s) => format!("<{}>", s),
-            BnfToken::Operator(BnfOperator::Def) => "::=".to_owned(),
-            BnfToken::Operator(BnfOperator::Alt) => "|".to_owned(),
-            BnfToken::Delimiter => ";".to_owned(),
+            BnfToken::Terminal(s) => write_terminal(w, s, quote),
+            BnfToken::NonTerminal(s) => write!(w, "<{}>", s),
+            BnfToken::Operator(BnfOperator::Def) => w.write_str("::="),
+            BnfToken::Operator(BnfOperator::Alt) => w.write_str("|"),
+            BnfToken::Delimiter => w.write_str(";"),
+        }
+    }
+}
+
+/// Write terminal `s` between `quote`s, so that the lexer of `quote`s reads it back.
+///
+/// Terminals which the lexer would not read back as is, e.g. with a quote inside, are written
+/// in tripled quotes, or else with their value escaped anew, e.g. `"\"a\"\\n"` for a terminal
+/// `"a"` followed by a newline.
+fn write_terminal<W: fmt::Write>(w: &mut W, s: &str, quote: char) -> fmt::Result {
+    if is_plain_terminal(s, quote) {
+        return write!(w, "{q}{}{q}", s, q = quote);
+    }
+    // tripled quotes end at the first tripled quote, escaped or not
+    let tripled = quote.to_string().repeat(3);
+    if !s.contains(&tripled) && !s.ends_with(quote) {
+        return write!(w, "{q}{}{q}", s, q = tripled);
+    }
+    w.write_char(quote)?;
+    for c in unescape(s).chars() {
+        match c {
+            '\n' => w.write_str("\\n")?,
+            '\\' => w.write_str("\\\\")?,
+            c if c == quote => write!(w, "\\{}", c)?,
+            c => w.write_char(c)?,
+        }
+    }
+    w.write_char(quote)
+}
+
+/// Whether the lexer of `quote`s reads `s` between single `quote`s back as is, i.e. there is no
+/// bare quote or newline in it, and every backslash starts an escape of `terminal_regex`.
+fn is_plain_terminal(s: &str, quote: char) -> bool {
+    let is_hex = |digits: &str| digits.bytes().all(|b| b.is_ascii_hexdigit());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                // `\xHH`
+                Some('x') => {
+                    let rest = chars.as_str();
+                    match rest.get(..2) {
+                        Some(digits) if is_hex(digits) => chars = rest[2..].chars(),
+                        _ => return false,
+                    }
+                }
+                // `\u{H}` to `\u{HHHHHH}`
+                Some('u') => {
+                    let rest = chars.as_str();
+                    let digits = rest
+                        .strip_prefix('{')
+                        .and_then(|r| r.find('}').map(|end| &r[..end]));
+                    match digits {
+                        Some(d) if !d.is_empty() && d.len() <= 6 && is_hex(d) => {
+                            chars = rest[d.len() + 2..].chars()
+                        }
+                        _ => return false,
+                    }
+                }
+                Some('\n') | None => return false,
+                Some(_) => {}
+            },
+            '\n' => return false,
+            c if c == quote => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+impl<'a> Token<'a> for BnfToken<'a> {
+    fn describe(&self) -> String {
+        self.to_string()
+    }
+
+    fn describe_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        write!(w, "{}", self)
+    }
+
+    fn identifier(&self) -> Option<&str> {
+        match *self {
+            BnfToken::NonTerminal(name) => Some(name),
+            _ => None,
         }
     }
 
@@ -77,6 +311,163 @@ impl<'a> Token<'a> for BnfToken<'a> {
     }
 }
 
+/// Format grammar of `tokens` canonically: one rule per line, with tokens separated by single
+/// spaces, and each rule terminated by a delimiter.
+///
+/// Lexing the result with `make_lexer` gives the same tokens back, plus the last delimiter if it
+/// was omitted. Terminals of other dialects, e.g. `'a"'` of `make_lexer_with_quotes('\'')`, may
+/// come back escaped differently, but with the same `unescaped` value.
+pub fn serialize(tokens: &[BnfToken]) -> String {
+    let mut serialized = String::new();
+    // whether the current rule has any tokens yet
+    let mut open = false;
+    for token in tokens {
+        if open {
+            serialized.push(' ');
+        }
+        serialized += &token.to_string();
+        open = *token != BnfToken::Delimiter;
+        if !open {
+            serialized.push('\n');
+        }
+    }
+    if open {
+        serialized += " ;\n";
+    }
+    serialized
+}
+
+/// Find non-terminals which are defined more than once in BNF `source`.
+///
+/// # Returns
+///
+/// Names of such non-terminals in order of their first definition, each with the lines of all
+/// of its definitions. Lexing stops at the first error, and only rules before it are checked.
+pub fn duplicate_definitions(source: &str) -> Vec<(String, Vec<usize>)> {
+    let mut definitions: Vec<(String, Vec<usize>)> = Vec::new();
+    let mut last: Option<TokenMeta<BnfToken>> = None;
+    for meta in make_lexer().into_tokens(source, "".into()) {
+        let meta = match meta {
+            Ok(meta) => meta,
+            Err(_) => break,
+        };
+        if let Some(ref last) = last {
+            if let (BnfToken::NonTerminal(name), BnfToken::Operator(BnfOperator::Def)) =
+                (last.token, meta.token)
+            {
+                match definitions.iter_mut().find(|(n, _)| n == name) {
+                    Some((_, lines)) => lines.push(last.line()),
+                    None => definitions.push((name.to_owned(), vec![last.line()])),
+                }
+            }
+        }
+        last = Some(meta);
+    }
+    definitions.retain(|(_, lines)| lines.len() > 1);
+    definitions
+}
+
+/// Insert delimiters at the ends of lines, for BNF dialects where a newline terminates a rule.
+///
+/// A delimiter is inserted right after a line's final token if it completes a rule, i.e. the
+/// rule's definition operator was seen and the token is a terminal or a non-terminal. Lines
+/// starting with an alternative operator `|` continue the rule of the previous line. Explicit
+/// delimiters are kept as they are.
+pub fn newline_delimiters<'a, I>(iter: I) -> NewlineDelimiters<'a, I>
+where
+    I: Iterator<Item = MetaResult<'a, BnfToken<'a>>>,
+{
+    NewlineDelimiters {
+        inner: iter,
+        poisoned: false,
+        pending: None,
+        last: None,
+        defined: false,
+    }
+}
+
+pub struct NewlineDelimiters<'a, I> {
+    inner: I,
+    poisoned: bool,
+    /// If the last token was implicit delimiter, this should contain the next token to return.
+    pending: Option<TokenMeta<BnfToken<'a>>>,
+    last: Option<TokenMeta<BnfToken<'a>>>,
+    /// Whether definition operator was seen since the last delimiter.
+    defined: bool,
+}
+
+impl<'a, I> NewlineDelimiters<'a, I> {
+    /// Implicit delimiter to insert after the last token, if it completes a rule.
+    fn delimiter(&self) -> Option<TokenMeta<BnfToken<'a>>> {
+        let last = self.last.as_ref().filter(|_| self.defined)?;
+        match last.token {
+            BnfToken::Terminal(..) | BnfToken::NonTerminal(..) => {}
+            _ => return None,
+        }
+        // delimiter spans directly after last token
+        let end = last.span.end;
+        let location = Location::new(end.line, end.column + 1, end.absolute + 1);
+        let end = last.position.end;
+        let position = Location::new(end.line, end.column + 1, end.absolute + 1);
+
+        Some(TokenMeta {
+            span: Span::from_location(location),
+            position: Span::from_location(position),
+            token: BnfToken::Delimiter,
+            implicit: true,
+            newline: false,
+            captures: Vec::new(),
+        })
+    }
+}
+
+impl<'a, I> Iterator for NewlineDelimiters<'a, I>
+where
+    I: Iterator<Item = MetaResult<'a, BnfToken<'a>>>,
+{
+    type Item = MetaResult<'a, BnfToken<'a>>;
+
+    fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+        if self.poisoned {
+            return None;
+        }
+
+        let meta = match self.pending.take() {
+            Some(meta) => meta,
+            None => match self.inner.next() {
+                Some(Ok(meta)) => {
+                    let continued = meta.token == BnfToken::Operator(BnfOperator::Alt);
+                    match self.delimiter().filter(|_| meta.newline && !continued) {
+                        Some(delimiter) => {
+                            self.pending = Some(meta);
+                            delimiter
+                        }
+                        None => meta,
+                    }
+                }
+                // the last delimiter
+                None => {
+                    let delimiter = self.delimiter();
+                    self.last = None;
+                    return delimiter.map(Ok);
+                }
+                // pass through
+                next @ Some(Err(..)) => {
+                    self.poisoned = true;
+                    return next;
+                }
+            },
+        };
+        match meta.token {
+            BnfToken::Operator(BnfOperator::Def) => self.defined = true,
+            BnfToken::Delimiter => self.defined = false,
+            _ => {}
+        }
+        self.last = Some(meta.clone());
+        Some(Ok(meta))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::BnfOperator::*;
@@ -110,4 +501,778 @@ mod tests {
 
         assert_eq!(tokens, TOKENS);
     }
+
+    #[test]
+    fn test_unescaped() {
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(r#""a\tb" "plain" <A> ;"#, FILENAME.into())
+            .into_raw()
+            .collect();
+
+        match tokens[0].unescaped() {
+            Cow::Owned(ref s) => assert_eq!(s, "a\tb"),
+            Cow::Borrowed(s) => panic!("expected owned value, got {:?}", s),
+        }
+        match tokens[1].unescaped() {
+            Cow::Borrowed(s) => assert_eq!(s, "plain"),
+            Cow::Owned(s) => panic!("expected borrowed value, got {:?}", s),
+        }
+        assert_eq!(tokens[2].unescaped(), "A");
+        assert_eq!(tokens[3].unescaped(), ";");
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(format!("{}", NonTerminal("X")), "<X>");
+        assert_eq!(format!("{}", Terminal("x")), "\"x\"");
+        assert_eq!(format!("{}", Operator(Alt)), "|");
+    }
+
+    #[test]
+    fn test_describe_all() {
+        let lexer = make_lexer();
+        let described = lexer.into_tokens(SOURCE, FILENAME.into()).describe_all();
+
+        assert_eq!(described, r#"<A> ::= <B> | "c" <D> ;"#);
+    }
+
+    #[test]
+    fn test_escaped_terminal() {
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(r#""a\"b" "\\""#, FILENAME.into())
+            .into_raw()
+            .collect();
+
+        assert_eq!(tokens, [Terminal(r#"a\"b"#), Terminal(r#"\\"#)]);
+        if let Terminal(t) = tokens[0] {
+            assert_eq!(unescape(t), "a\"b");
+        }
+        if let Terminal(t) = tokens[1] {
+            assert_eq!(unescape(t), "\\");
+        }
+    }
+
+    #[test]
+    fn test_hex_escapes() {
+        let tokens = make_lexer()
+            .into_tokens(r#""\x41" "\u{1F600}" "a\r""#, FILENAME.into())
+            .tokens_only();
+        assert_eq!(tokens, [Terminal(r"\x41"), Terminal(r"\u{1F600}"), Terminal(r"a\r")]);
+        assert_eq!(tokens[0].unescaped(), "A");
+        assert_eq!(tokens[1].unescaped(), "😀");
+
+        // malformed hexadecimal escapes are not terminals
+        for &source in [r#""\x""#, r#""\x4""#, r#""\u""#, r#""\u{}""#, r#""\u{1234567}""#].iter() {
+            let tokens: Vec<_> = make_lexer().into_tokens(source, FILENAME.into()).collect();
+            assert!(tokens[0].is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_lex_to_vec() {
+        let source = "<A> ::= @ <B> ;";
+
+        let (tokens, errors) = make_lexer().lex_to_vec(source, FILENAME.into());
+        let tokens: Vec<_> = tokens.into_iter().map(|meta| meta.token).collect();
+        assert_eq!(tokens, [NonTerminal("A"), Operator(Def)]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span.range(), 8..9);
+        assert_eq!(errors[0].filename, FILENAME);
+
+        let lexer = lexer_builder().error_recovery(true).build().unwrap();
+        let (tokens, errors) = lexer.lex_to_vec(source, FILENAME.into());
+        let tokens: Vec<_> = tokens.into_iter().map(|meta| meta.token).collect();
+        assert_eq!(tokens, [NonTerminal("A"), Operator(Def), NonTerminal("B"), Delimiter]);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let tokens = make_lexer().into_tokens(SOURCE, FILENAME.into()).tokens_only();
+        let serialized = serialize(&tokens);
+        assert_eq!(serialized, "<A> ::= <B> | \"c\" <D> ;\n");
+
+        let lexed = make_lexer().into_tokens(&serialized, FILENAME.into()).tokens_only();
+        assert_eq!(lexed, tokens);
+        assert_eq!(serialize(&lexed), serialized);
+
+        // delimiter is appended after the last rule
+        let source = "<A>::=\"x\"|<B>;<B>\n  ::= \"\"\"multi\nline\"\"\"";
+        let tokens = make_lexer().into_tokens(source, FILENAME.into()).tokens_only();
+        let serialized = serialize(&tokens);
+        assert_eq!(
+            serialized,
+            "<A> ::= \"x\" | <B> ;\n<B> ::= \"\"\"multi\nline\"\"\" ;\n"
+        );
+        let lexed = make_lexer().into_tokens(&serialized, FILENAME.into()).tokens_only();
+        assert_eq!(lexed[..tokens.len()], tokens[..]);
+        assert_eq!(lexed[tokens.len()..], [Delimiter]);
+
+        // terminals with double quotes inside are kept in tripled quotes
+        let source = r#"<A> ::= """a "b" c""" ;"#;
+        let tokens = make_lexer().into_tokens(source, FILENAME.into()).tokens_only();
+        assert_eq!(tokens[2], Terminal(r#"a "b" c"#));
+        let serialized = serialize(&tokens);
+        assert_eq!(serialized, format!("{}\n", source));
+        let lexed = make_lexer().into_tokens(&serialized, FILENAME.into()).tokens_only();
+        assert_eq!(lexed, tokens);
+
+        // or escaped if tripled quotes would end early
+        let source = r#"<A> ::= 'a"' | 'b"""' ;"#;
+        let lexer = make_lexer_with_quotes('\'');
+        let tokens = lexer.into_tokens(source, FILENAME.into()).tokens_only();
+        let serialized = serialize(&tokens);
+        assert_eq!(serialized, r#"<A> ::= "a\"" | "b\"\"\"" ;"#.to_owned() + "\n");
+        let lexed = make_lexer().into_tokens(&serialized, FILENAME.into()).tokens_only();
+        let values = |tokens: &[BnfToken]| -> Vec<String> {
+            tokens.iter().map(|t| t.unescaped().into_owned()).collect()
+        };
+        assert_eq!(values(&lexed), values(&tokens));
+    }
+
+    #[test]
+    fn test_is_plain_terminal() {
+        let regex = regex::Regex::new(&format!("^{}$", terminal_regex('"'))).unwrap();
+        let terminals = [
+            "", "x", r#"a\"b"#, r"\\", r"\x41", r"\x4", r"\xZZ", r"\u{1F600}", r"\u{}",
+            r"\u{1234567}", r"\u{12", r"\u", r"\'", "a\\", r#"a"b"#, "a\nb", "a\\\nb", "é\\é",
+        ];
+        for t in &terminals {
+            let quoted = format!("\"{}\"", t);
+            assert_eq!(is_plain_terminal(t, '"'), regex.is_match(&quoted), "{}", quoted);
+        }
+    }
+
+    #[test]
+    fn test_coalesce_errors() {
+        let lexer = lexer_builder().error_recovery(true).build().unwrap();
+
+        let results: Vec<_> = lexer
+            .tokens("<A> ::= $$$ <B> $ ;", FILENAME.into())
+            .coalesce_errors()
+            .collect();
+        let errors: Vec<_> = results
+            .iter()
+            .filter_map(|r| r.as_ref().err())
+            .map(|e| e.span.range())
+            .collect();
+        assert_eq!(errors, [8..11, 16..17]);
+        assert_eq!(results.len(), 6);
+        assert_eq!(results[3].as_ref().unwrap().token, NonTerminal("B"));
+    }
+
+    #[test]
+    fn test_next_with_len() {
+        let lexer = make_lexer();
+
+        let (len, meta) = lexer.next_with_len("<A>").unwrap().unwrap();
+        assert_eq!(len, "<A>".len());
+        assert_eq!(meta.token, NonTerminal("A"));
+
+        // caller advances its own cursor past whitespaces and the token
+        let source = "  <A> ::= @";
+        let mut cursor = 0;
+        let mut tokens = vec![];
+        while let Some(Ok((len, meta))) = lexer.next_with_len(&source[cursor..]) {
+            cursor += len;
+            tokens.push(meta.token);
+        }
+        assert_eq!(tokens, [NonTerminal("A"), Operator(Def)]);
+        assert_eq!(&source[cursor..], " @");
+        assert!(lexer.next_with_len(&source[cursor..]).unwrap().is_err());
+        assert!(lexer.next_with_len("  ").is_none());
+    }
+
+    #[test]
+    fn test_relex() {
+        use lex::TokenMeta;
+
+        let old = "<A> ::= <B> | \"c\" ;\n<B> ::= \"d\" ;";
+        let new = "<A> ::= <B> | \"xy\" ;\n<B> ::= \"d\" ;";
+        let lexer = lexer_builder().keep_captures(true).build().unwrap();
+        let (old_tokens, _) = lexer.lex_to_vec(old, FILENAME.into());
+
+        // "c" is replaced with "xy"
+        let tokens = lexer.relex(&old_tokens, new, 15..16, 2);
+        let (expected, _) = lexer.lex_to_vec(new, FILENAME.into());
+        assert_eq!(format!("{:?}", tokens), format!("{:?}", expected));
+        let captures: Vec<_> = tokens.iter().map(|meta| meta.captures.clone()).collect();
+        let expected: Vec<_> = expected.into_iter().map(|meta| meta.captures).collect();
+        assert_eq!(captures, expected);
+
+        // only the terminal and the delimiter after it are lexed again, the rest borrow from
+        // the old source
+        let in_old = |meta: &TokenMeta<BnfToken>| match meta.token {
+            NonTerminal(s) | Terminal(s) => old.as_bytes().as_ptr_range().contains(&s.as_ptr()),
+            _ => true,
+        };
+        assert!(tokens[..4].iter().all(in_old));
+        assert_eq!(tokens[4].token, Terminal("xy"));
+        assert!(!in_old(&tokens[4]));
+        assert!(tokens[6..].iter().all(in_old));
+        assert_eq!(tokens[6].token, NonTerminal("B"));
+
+        // lines after an inserted newline are shifted
+        let new = "<A> ::= <B> | \"c\"\n   ;\n<B> ::= \"d\" ;";
+        let tokens = lexer.relex(&old_tokens, new, 17..17, 3);
+        let (expected, _) = lexer.lex_to_vec(new, FILENAME.into());
+        assert_eq!(format!("{:?}", tokens), format!("{:?}", expected));
+        assert_eq!(tokens[6].line(), 3);
+    }
+
+    #[test]
+    fn test_multiline_terminal() {
+        let source = "<A> ::= \"\"\"first\nsecond\"\"\" | \"x\" ;";
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(source, FILENAME.into())
+            .into_raw()
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                NonTerminal("A"),
+                Operator(Def),
+                Terminal("first\nsecond"),
+                Operator(Alt),
+                Terminal("x"),
+                Delimiter,
+            ]
+        );
+
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens("<A> ::= \"first\nsecond\" ;", FILENAME.into())
+            .collect();
+        assert!(tokens.iter().any(Result::is_err));
+    }
+
+    #[test]
+    fn test_as_nonterminal() {
+        let names: Vec<_> = make_lexer()
+            .into_tokens(SOURCE, FILENAME.into())
+            .into_raw()
+            .filter_map(|t| t.as_nonterminal())
+            .collect();
+        assert_eq!(names, ["A", "B", "D"]);
+
+        let terminals: Vec<_> = make_lexer()
+            .into_tokens(SOURCE, FILENAME.into())
+            .into_raw()
+            .filter_map(|t| t.as_terminal())
+            .collect();
+        assert_eq!(terminals, ["c"]);
+        assert_eq!(Delimiter.as_terminal(), None);
+    }
+
+    #[test]
+    fn test_split_on() {
+        let lexer = make_lexer();
+        let describe = |source| {
+            lexer
+                .tokens(source, FILENAME.into())
+                .split_on(|t| *t == Delimiter)
+                .map(|group| {
+                    let group = group.unwrap().into_iter().map(|meta| meta.token);
+                    group.map(|t| t.describe()).collect::<Vec<_>>().join(" ")
+                }).collect::<Vec<_>>()
+        };
+
+        let expected = [r#"<A> ::= <B> | "c""#, r#"<B> ::= "d""#];
+        assert_eq!(describe(r#"<A> ::= <B> | "c" ; <B> ::= "d""#), expected);
+        assert_eq!(describe(r#"<A> ::= <B> | "c" ; <B> ::= "d" ;"#), expected);
+    }
+
+    #[test]
+    fn test_reader() {
+        use lex::OwnedToken;
+        use std::io::Cursor;
+
+        let expected: Vec<_> = make_lexer()
+            .into_tokens(SOURCE, FILENAME.into())
+            .map(|meta| {
+                let owned = meta.unwrap().map(|token| OwnedToken::new(&token));
+                format!("{:?}", owned)
+            })
+            .collect();
+        let tokens: Vec<_> = BnfLexer::default()
+            .tokens_from_reader(Cursor::new(SOURCE), FILENAME.into())
+            .chunk_size(4)
+            .map(|meta| format!("{:?}", meta.unwrap()))
+            .collect();
+
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        use lex::Span;
+        use std::io::Cursor;
+
+        const SOURCE: &str = "<A> ::= ? <B> ;";
+
+        let results: Vec<_> = lexer_builder()
+            .error_recovery(true)
+            .build()
+            .unwrap()
+            .into_tokens(SOURCE, FILENAME.into())
+            .collect();
+
+        assert_eq!(results.len(), 5);
+        let errors: Vec<_> = results.iter().filter_map(|r| r.as_ref().err()).collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span.range(), 8..9);
+        assert_eq!(&SOURCE[errors[0].span.range()], "?");
+        assert_eq!(errors[0].filename, FILENAME);
+
+        let tokens: Vec<_> = results.iter().filter_map(|r| r.as_ref().ok()).collect();
+        assert_eq!(tokens[2].token, BnfToken::NonTerminal("B"));
+        assert_eq!(tokens[2].span, Span::from(SOURCE, 10, 12));
+        assert_eq!(tokens[2].position.start.column, 11);
+        assert_eq!(tokens[3].token, BnfToken::Delimiter);
+
+        // streaming lexer recovers the same way
+        let streamed: Vec<_> = BnfLexer(|builder| builder.error_recovery(true))
+            .tokens_from_reader(Cursor::new(SOURCE), FILENAME.into())
+            .chunk_size(3)
+            .map(|r| r.map(|meta| meta.span).map_err(|e| e.span))
+            .collect();
+        let expected: Vec<_> = results
+            .iter()
+            .map(|r| r.as_ref().map(|meta| meta.span).map_err(|e| e.span))
+            .collect();
+        assert_eq!(streamed, expected);
+
+        // without recovery the stream ends at the error
+        let tokens: Vec<_> = make_lexer().into_tokens(SOURCE, FILENAME.into()).collect();
+        assert_eq!(tokens.len(), 3);
+        assert!(tokens[2].is_err());
+    }
+
+    #[test]
+    fn test_keep_captures() {
+        use std::io::Cursor;
+
+        const SOURCE: &str = "  <Name> ::= \"x\"";
+        let lexer = lexer_builder().keep_captures(true).build().unwrap();
+
+        let tokens: Vec<_> = lexer.tokens(SOURCE, FILENAME.into()).map(Result::unwrap).collect();
+        assert_eq!(tokens[0].captures, [Some(2..8), Some(3..7)]);
+        assert_eq!(&SOURCE[tokens[0].captures[1].clone().unwrap()], "Name");
+        assert_eq!(tokens[1].captures, [Some(9..12)]);
+
+        let streamed: Vec<_> = BnfLexer(|builder| builder.keep_captures(true))
+            .tokens_from_reader(Cursor::new(SOURCE), FILENAME.into())
+            .chunk_size(3)
+            .map(|meta| meta.unwrap().captures)
+            .collect();
+        let expected: Vec<_> = tokens.into_iter().map(|meta| meta.captures).collect();
+        assert_eq!(streamed, expected);
+
+        // not recorded by default
+        let meta = make_lexer().tokens(SOURCE, FILENAME.into()).next().unwrap().unwrap();
+        assert!(meta.captures.is_empty());
+    }
+
+    #[test]
+    fn test_max_tokens() {
+        use std::io::Cursor;
+
+        const SOURCE: &str = r#"<A> ::= "x" | <B> ;"#;
+        let lexer = lexer_builder().max_tokens(3).build().unwrap();
+
+        let results: Vec<_> = lexer.tokens(SOURCE, FILENAME.into()).collect();
+        assert_eq!(results.len(), 4);
+        assert!(results[..3].iter().all(|r| r.is_ok()));
+        let error = results[3].as_ref().unwrap_err();
+        assert_eq!(&SOURCE[error.span.range()], "|");
+
+        let streamed: Vec<_> = BnfLexer(|builder| builder.max_tokens(3))
+            .tokens_from_reader(Cursor::new(SOURCE), FILENAME.into())
+            .map(|r| r.map(|meta| meta.span).map_err(|e| e.span))
+            .collect();
+        let expected: Vec<_> = results
+            .iter()
+            .map(|r| r.as_ref().map(|meta| meta.span).map_err(|e| e.span))
+            .collect();
+        assert_eq!(streamed, expected);
+
+        // limit is inclusive
+        let tokens: Vec<_> = lexer.tokens("<A> ::= <B>", FILENAME.into()).collect();
+        assert_eq!(tokens.len(), 3);
+        assert!(tokens.iter().all(|r| r.is_ok()));
+    }
+
+    #[test]
+    fn test_dump() {
+        let dump = make_lexer().into_tokens(SOURCE, FILENAME.into()).dump();
+        let lines: Vec<_> = dump.lines().collect();
+
+        assert_eq!(lines.len(), TOKENS.len());
+        assert_eq!(lines[..2], ["2:9 NonTerminal <A>", "2:13 ::= ::="]);
+
+        let dump = make_lexer().into_tokens("<A> ?", FILENAME.into()).dump();
+        assert_eq!(dump, "1:1 NonTerminal <A>\n!:! No token could be matched at byte 4: \"?\"\n");
+    }
+
+    #[test]
+    fn test_quotes() {
+        let source = r#"<A> ::= 'x' | 'say "hi"' | 'it\'s' ;"#;
+        let lexer = make_lexer_with_quotes('\'');
+        let tokens: Vec<_> = lexer.tokens(source, FILENAME.into()).into_raw().collect();
+
+        assert_eq!(
+            tokens,
+            [
+                NonTerminal("A"),
+                Operator(Def),
+                Terminal("x"),
+                Operator(Alt),
+                Terminal(r#"say "hi""#),
+                Operator(Alt),
+                Terminal(r"it\'s"),
+                Delimiter,
+            ]
+        );
+        assert_eq!(tokens[6].unescaped(), "it's");
+
+        let described: Vec<_> = tokens.iter().map(|t| t.describe_with_quote('\'')).collect();
+        assert_eq!(described.join(" "), source);
+
+        // double quotes are not terminals then
+        let tokens: Vec<_> = lexer.tokens(r#""x""#, FILENAME.into()).collect();
+        assert!(tokens[0].is_err());
+    }
+
+    #[test]
+    fn test_describe_into() {
+        let mut buffer = String::new();
+        for token in make_lexer().into_tokens(SOURCE, FILENAME.into()).into_raw() {
+            token.describe_into(&mut buffer).unwrap();
+        }
+
+        let described: String = TOKENS.iter().map(Token::describe).collect();
+        assert_eq!(buffer, described);
+        assert_eq!(buffer, r#"<A>::=<B>|"c"<D>;"#);
+    }
+
+    #[test]
+    fn test_skip_shebang() {
+        let shebang = format!("#!/usr/bin/env bnf\n{}", SOURCE);
+        let lexer = lexer_builder().skip_shebang(true).build().unwrap();
+
+        let tokens: Vec<_> = lexer.tokens(&shebang, FILENAME.into()).into_raw().collect();
+        assert_eq!(tokens, TOKENS);
+
+        let meta = lexer.tokens(&shebang, FILENAME.into()).next().unwrap().unwrap();
+        assert_eq!(meta.line(), 3);
+        assert_eq!(&shebang[meta.range()], "<A>");
+
+        // not anywhere else
+        let tokens: Vec<_> = lexer.tokens("<A> #!", FILENAME.into()).collect();
+        assert!(tokens[1].is_err());
+        // nor by default
+        let tokens: Vec<_> = make_lexer().tokens(&shebang, FILENAME.into()).collect();
+        assert!(tokens[0].is_err());
+        // shebang alone is an empty source
+        assert_eq!(lexer.tokens("#!bnf", FILENAME.into()).count(), 0);
+    }
+
+    #[test]
+    fn test_tab_width() {
+        let source = "\t<A>\t::= \"a\"\n\t\t| <B>";
+        let lexer = lexer_builder().tab_width(4).build().unwrap();
+
+        let columns: Vec<_> = lexer
+            .tokens(source, FILENAME.into())
+            .map(|meta| meta.unwrap().column())
+            .collect();
+        assert_eq!(columns, [5, 9, 13, 9, 11]);
+
+        // same when reading incrementally
+        let reader = BnfLexer(|builder| builder.tab_width(4))
+            .tokens_from_reader(source.as_bytes(), FILENAME.into());
+        let columns: Vec<_> = reader.map(|meta| meta.unwrap().column()).collect();
+        assert_eq!(columns, [5, 9, 13, 9, 11]);
+
+        // and for trivia, e.g. the tabs themselves
+        let trivia: Vec<_> = lexer
+            .lossless_tokens(source, FILENAME.into())
+            .map(|meta| meta.unwrap().position.end.column)
+            .collect();
+        assert_eq!(trivia, [4, 7, 8, 11, 12, 15, 8, 9, 10, 13]);
+
+        // tab is one column by default
+        let meta = make_lexer().tokens(source, FILENAME.into()).next().unwrap().unwrap();
+        assert_eq!(meta.column(), 2);
+        // byte spans are never affected
+        let meta = lexer.tokens(source, FILENAME.into()).next().unwrap().unwrap();
+        assert_eq!(meta.span.start.column, 2);
+    }
+
+    #[test]
+    fn test_fuse_strings() {
+        use lex::Fused;
+
+        let source = r#"<A> ::= "ab" "c\"d" | "e" ; <B> ::= "f" "g""#;
+        let terminal = |t: &BnfToken<'static>| match *t {
+            Terminal(..) => Some(t.unescaped()),
+            _ => None,
+        };
+        let tokens = make_lexer()
+            .into_tokens(source, FILENAME.into())
+            .fuse_strings(terminal)
+            .try_collect()
+            .unwrap();
+
+        let raw: Vec<_> = tokens.iter().map(|meta| meta.token.clone()).collect();
+        assert_eq!(
+            raw,
+            [
+                Fused::Token(NonTerminal("A")),
+                Fused::Token(Operator(Def)),
+                Fused::Strings("Terminal", "abc\"d".into()),
+                Fused::Token(Operator(Alt)),
+                Fused::Token(Terminal("e")),
+                Fused::Token(Delimiter),
+                Fused::Token(NonTerminal("B")),
+                Fused::Token(Operator(Def)),
+                Fused::Strings("Terminal", "fg".into()),
+            ]
+        );
+        assert_eq!(&source[tokens[2].range()], r#""ab" "c\"d""#);
+        assert_eq!(&source[tokens[8].range()], r#""f" "g""#);
+    }
+
+    #[test]
+    fn test_reversed() {
+        let forward: Vec<_> = make_lexer()
+            .into_tokens(SOURCE, FILENAME.into())
+            .map(Result::unwrap)
+            .collect();
+        let backward: Vec<_> = make_lexer()
+            .into_tokens(SOURCE, FILENAME.into())
+            .reversed()
+            .map(Result::unwrap)
+            .collect();
+
+        let tokens: Vec<_> = backward.iter().map(|meta| meta.token).collect();
+        let mut expected = TOKENS.to_vec();
+        expected.reverse();
+        assert_eq!(tokens, expected);
+
+        for (b, f) in backward.iter().zip(forward.iter().rev()) {
+            assert_eq!(b.span, f.span);
+            assert_eq!(b.position, f.position);
+        }
+        assert!(backward.windows(2).all(|w| w[0].span.start > w[1].span.end));
+
+        // error ends the stream, so it goes first
+        let last = make_lexer().into_tokens("<A> ::= @", FILENAME.into()).reversed().next();
+        assert!(last.unwrap().is_err());
+    }
+
+    #[test]
+    fn test_tokens_only() {
+        let tokens = make_lexer().into_tokens(SOURCE, FILENAME.into()).tokens_only();
+        assert_eq!(tokens, TOKENS);
+
+        // the same tokens anywhere else
+        let shifted = format!("\n\n    {}", SOURCE.trim());
+        let tokens = make_lexer().into_tokens(&shifted, FILENAME.into()).tokens_only();
+        assert_eq!(tokens, TOKENS);
+
+        let meta = make_lexer().into_tokens(&shifted, FILENAME.into()).next().unwrap().unwrap();
+        assert!(meta.eq_token(&NonTerminal("A")));
+        assert!(!meta.eq_token(&NonTerminal("B")));
+    }
+
+    #[test]
+    fn test_try_collect() {
+        let tokens = make_lexer().into_tokens(SOURCE, FILENAME.into()).try_collect().unwrap();
+        let tokens: Vec<_> = tokens.into_iter().map(|meta| meta.token).collect();
+        assert_eq!(tokens, TOKENS);
+
+        let error = make_lexer()
+            .into_tokens("<A> ::= @ <B>", FILENAME.into())
+            .try_collect()
+            .unwrap_err();
+        assert_eq!(error.span.range(), 8..9);
+        assert_eq!(error.filename, FILENAME);
+    }
+
+    #[test]
+    fn test_with_eof() {
+        let source = "<A> ::= \"a\" ;\n  ";
+        let tokens = make_lexer()
+            .into_tokens(source, FILENAME.into())
+            .with_eof(source)
+            .try_collect()
+            .unwrap();
+
+        let raw: Vec<_> = tokens.iter().map(|meta| meta.token).collect();
+        assert_eq!(
+            raw,
+            [
+                Some(NonTerminal("A")),
+                Some(Operator(Def)),
+                Some(Terminal("a")),
+                Some(Delimiter),
+                None,
+            ]
+        );
+        let eof = tokens.last().unwrap();
+        assert_eq!(eof.span.start.absolute, source.len());
+        assert_eq!(eof.position.start.absolute, source.chars().count());
+        assert_eq!(eof.span.start.line, 2);
+        assert!(eof.implicit);
+
+        // no end of input after an error
+        let source = "<A> ::= @";
+        let last = make_lexer()
+            .into_tokens(source, FILENAME.into())
+            .with_eof(source)
+            .last()
+            .unwrap();
+        assert!(last.is_err());
+
+        // column of the end agrees with tab stops of tokens
+        let source = "<A> ::=\t\"a\"\t";
+        let tokens = lexer_builder()
+            .tab_width(8)
+            .build()
+            .unwrap()
+            .into_tokens(source, FILENAME.into())
+            .with_eof(source)
+            .tab_width(8)
+            .try_collect()
+            .unwrap();
+        let columns: Vec<_> = tokens.iter().map(|meta| meta.column()).collect();
+        assert_eq!(columns, [1, 5, 9, 17]);
+    }
+
+    #[test]
+    fn test_newline_delimiters() {
+        let source = "
+            <A> ::= <B> \"c\"
+                  | \"d\"
+            <B> ::=
+                \"e\"
+        ";
+        let tokens = || newline_delimiters(make_lexer().into_tokens(source, FILENAME.into()));
+
+        let rules: Vec<_> = tokens()
+            .split_on(|t| *t == Delimiter)
+            .map(|rule| {
+                let rule = rule.unwrap().into_iter().map(|meta| meta.token.describe());
+                rule.collect::<Vec<_>>().join(" ")
+            }).collect();
+        assert_eq!(rules, [r#"<A> ::= <B> "c" | "d""#, r#"<B> ::= "e""#]);
+
+        let tokens: Vec<_> = tokens().map(Result::unwrap).collect();
+        assert_eq!(tokens.iter().filter(|meta| meta.token == Delimiter).count(), 2);
+        let implicit: Vec<_> = tokens.iter().filter(|meta| meta.implicit).collect();
+        assert_eq!(implicit.len(), 2);
+        assert_eq!(implicit[0].token, Delimiter);
+        assert_eq!(implicit[0].line(), 3);
+        assert_eq!(implicit[1].line(), 5);
+
+        // explicit delimiters are not doubled
+        let source = "<A> ::= \"x\";\n<B> ::= \"y\" ;";
+        let described = newline_delimiters(make_lexer().into_tokens(source, FILENAME.into()))
+            .describe_all();
+        assert_eq!(described, r#"<A> ::= "x" ; <B> ::= "y" ;"#);
+    }
+
+    #[test]
+    fn test_bom() {
+        let with_bom = format!("\u{FEFF}{}", SOURCE);
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(&with_bom, FILENAME.into())
+            .into_raw()
+            .collect();
+        assert_eq!(tokens, TOKENS);
+
+        let first = make_lexer().into_tokens(&with_bom, FILENAME.into()).next().unwrap();
+        assert_eq!(&with_bom[first.unwrap().span.range()], "<A>");
+
+        // only at the start of the source
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens("<A> \u{FEFF}::=", FILENAME.into())
+            .collect();
+        assert_eq!(tokens.len(), 2);
+        assert!(tokens[1].is_err());
+    }
+
+    #[test]
+    fn test_into_owned() {
+        let source = String::from(SOURCE);
+        let owned: Vec<_> = make_lexer()
+            .tokens(&source, FILENAME.into())
+            .into_raw()
+            .map(BnfToken::into_owned)
+            .collect();
+        drop(source);
+
+        assert_eq!(owned[0], OwnedBnfToken::NonTerminal("A".into()));
+        assert_eq!(owned[4].as_token().unescaped(), "c");
+        let tokens: Vec<_> = owned.iter().map(OwnedBnfToken::as_token).collect();
+        assert_eq!(tokens, TOKENS);
+    }
+
+    #[test]
+    fn test_token_at() {
+        let source = r#"<If> ::= "if" <Condition> "then" ;"#;
+        let lexer = make_lexer();
+        let offset = source.find("Cond").unwrap() + 2;
+
+        let meta = lexer.token_at(source, FILENAME.into(), offset).unwrap();
+        assert_eq!(meta.token, NonTerminal("Condition"));
+        assert_eq!(&source[meta.span.range()], "<Condition>");
+
+        let first = lexer.token_at(source, FILENAME.into(), 0).unwrap();
+        assert_eq!(first.token, NonTerminal("If"));
+        // between tokens and past the end
+        assert!(lexer.token_at(source, FILENAME.into(), 4).is_none());
+        assert!(lexer.token_at(source, FILENAME.into(), source.len()).is_none());
+    }
+
+    #[test]
+    fn test_map_token() {
+        let tokens = || make_lexer().into_tokens(SOURCE, FILENAME.into());
+        let mapped: Vec<_> = tokens()
+            .map_token(|t| t.descriptor())
+            .map(Result::unwrap)
+            .collect();
+
+        let kinds: Vec<_> = mapped.iter().map(|meta| meta.token).collect();
+        assert_eq!(
+            kinds,
+            ["NonTerminal", "::=", "NonTerminal", "|", "Terminal", "NonTerminal", ";"]
+        );
+        for (mapped, original) in mapped.iter().zip(tokens().map(Result::unwrap)) {
+            assert_eq!(mapped.span, original.span);
+            assert_eq!(mapped.position, original.position);
+        }
+
+        let mut errors = make_lexer()
+            .into_tokens("<A> ?", FILENAME.into())
+            .map_token(|t| t.descriptor());
+        assert!(errors.next().unwrap().is_ok());
+        assert!(errors.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_duplicate_definitions() {
+        let source = r#"
+            <A> ::= "x" ;
+            <B> ::= <A> ;
+            <A> ::= "y" ;
+            <A> ::= "z"
+        "#;
+
+        assert_eq!(
+            duplicate_definitions(source),
+            [("A".to_owned(), vec![2, 4, 5])]
+        );
+        assert!(duplicate_definitions(SOURCE).is_empty());
+    }
 }
@@ -0,0 +1,138 @@
+//! # Extended BNF lexer with grouping
+//!
+//! Same as [BNF](../bnf/index.html), plus grouping parenthesis (`(`, `)`) for inline
+//! alternatives, e.g. `<A> ::= ("x" | "y") <B> ;`. Groups are the same as in
+//! [EBNF](../ebnf/index.html), but there are no repetitions or options.
+//!
+//! Strict BNF lexer is left intact, so that grouping is only accepted on request.
+pub use lang::bnf::BnfOperator;
+pub use lang::ebnf::Side;
+use lang::{terminal_regex, unescape};
+use lex::{ascii_whitespace_filter, Lexer, LexerBuilder, Token};
+use std::borrow::Cow;
+use std::fmt;
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub enum BnfExtToken<'a> {
+    /// Terminal as written between its quotes, escapes included; see [`unescape`].
+    ///
+    /// [`unescape`]: ../fn.unescape.html
+    Terminal(&'a str),
+    NonTerminal(&'a str),
+    Operator(BnfOperator),
+    Group(Side),
+    Delimiter,
+}
+
+pub fn make_lexer<'a>() -> Lexer<'a, BnfExtToken<'a>> {
+    lexer_builder().build().unwrap()
+}
+
+/// Rules of `make_lexer`, for further tuning of the lexer.
+pub fn lexer_builder<'a>() -> LexerBuilder<'a, BnfExtToken<'a>> {
+    LexerBuilder::new()
+        .skip_whitespaces(ascii_whitespace_filter)
+        .add(r";", constant!(BnfExtToken::Delimiter))
+        .add(r"::=", constant!(BnfExtToken::Operator(BnfOperator::Def)))
+        .add(r"\|", constant!(BnfExtToken::Operator(BnfOperator::Alt)))
+        .add(r"\(", constant!(BnfExtToken::Group(Side::Start)))
+        .add(r"\)", constant!(BnfExtToken::Group(Side::End)))
+        .add(r"<(.+?)>", |c| {
+            BnfExtToken::NonTerminal(c.get(1).unwrap().as_str())
+        }).add(&terminal_regex('"'), |c| {
+            BnfExtToken::Terminal(c.get(1).unwrap().as_str())
+        })
+}
+
+impl<'a> BnfExtToken<'a> {
+    /// Same as [`BnfToken::unescaped`](../bnf/enum.BnfToken.html#method.unescaped).
+    pub fn unescaped(&self) -> Cow<'a, str> {
+        match *self {
+            BnfExtToken::Terminal(t) => unescape(t),
+            BnfExtToken::NonTerminal(t) => Cow::Borrowed(t),
+            _ => Cow::Borrowed(self.descriptor()),
+        }
+    }
+}
+
+impl<'a> fmt::Display for BnfExtToken<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BnfExtToken::Terminal(s) => write!(f, "\"{}\"", s),
+            BnfExtToken::NonTerminal(s) => write!(f, "<{}>", s),
+            _ => f.write_str(self.descriptor()),
+        }
+    }
+}
+
+impl<'a> Token<'a> for BnfExtToken<'a> {
+    fn describe(&self) -> String {
+        self.to_string()
+    }
+
+    fn describe_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        write!(w, "{}", self)
+    }
+
+    fn descriptor(&self) -> &'static str {
+        match self {
+            BnfExtToken::Terminal(..) => "Terminal",
+            BnfExtToken::NonTerminal(..) => "NonTerminal",
+            BnfExtToken::Operator(BnfOperator::Def) => "::=",
+            BnfExtToken::Operator(BnfOperator::Alt) => "|",
+            BnfExtToken::Group(Side::Start) => "(",
+            BnfExtToken::Group(Side::End) => ")",
+            BnfExtToken::Delimiter => ";",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BnfExtToken::*;
+    use super::BnfOperator::*;
+    use super::Side::*;
+    use super::*;
+    use lex::TokensExt;
+
+    const FILENAME: &str = "test.bnf";
+
+    #[test]
+    fn test_group() {
+        let source = r#"<A> ::= ("x" | "y") <B> ;"#;
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(source, FILENAME.into())
+            .into_raw()
+            .collect();
+
+        assert_eq!(
+            tokens,
+            [
+                NonTerminal("A"),
+                Operator(Def),
+                Group(Start),
+                Terminal("x"),
+                Operator(Alt),
+                Terminal("y"),
+                Group(End),
+                NonTerminal("B"),
+                Delimiter,
+            ]
+        );
+
+        let described = make_lexer().into_tokens(source, FILENAME.into()).describe_all();
+        assert_eq!(described, r#"<A> ::= ( "x" | "y" ) <B> ;"#);
+    }
+
+    #[test]
+    fn test_strict_bnf() {
+        use lang::bnf;
+
+        let tokens: Vec<_> = bnf::make_lexer()
+            .into_tokens(r#"<A> ::= ("x")"#, FILENAME.into())
+            .collect();
+
+        assert!(tokens.last().unwrap().is_err());
+    }
+}
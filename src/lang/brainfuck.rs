@@ -44,6 +44,7 @@ pub fn make_lexer<'a>() -> Lexer<'a, BfToken<'a>> {
         .add(r"[^<>\[\],.+\-]+", |c| {
             BfToken::Comment(c.get(0).unwrap().as_str())
         }).build()
+        .unwrap()
 }
 
 #[cfg(test)]
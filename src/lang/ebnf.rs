@@ -1,90 +1,248 @@
 //! # EBNF lexer
 //!
 //! EBNF (or Extended Backus-Naur Form) language consists of the following lexemes:
-//! - terminals (e.g.: `"fn"`, `">="`);
+//! - terminals (e.g.: `"fn"`, `">="`, `"\""`), where a backslash escapes a double quote
+//!   or another backslash;
 //! - non-terminals (e.g.: `<Condition>`, `<Rule>`);
-//! - 2 operators, namely: 'definition' (`::=`) and 'alternative' (`|`);
-//! - repetitions (`{`, `}`);
+//! - 4 operators, namely: 'definition' (`::=`), 'alternative' (`|`),
+//!   'range' (`..`), the latter written between two terminals (e.g.: `"a" .. "z"`),
+//!   and 'exception' (`-`) as in ISO EBNF (e.g.: `<Any> - "x"`);
+//! - repetitions (`{`, `}`) and repetition counts (e.g.: `4 *` in `4 * <Digit>`);
 //! - options (`[`, `]`);
 //! - grouping parenthesis (`(`, `)`);
+//! - special sequences between question marks (e.g.: `? any visible character ?`);
 //! - rules delimiter: a semicolon (`;`);
-//! - comment: everything after `//` until the end of line.
+//! - comments: everything after `//` until the end of line, or between `/*` and `*/`,
+//!   or between `(*` and `*)` as in ISO EBNF. Block comments nest, so
+//!   `(* a (* b *) c *)` is a single comment.
 //!
 //! Delimiter is optional after the last rule.
 pub use self::{EbnfOperator::*, EbnfToken::*, Side::*};
-use lex::{Lexer, LexerBuilder, MetaIter, Token};
+use lang::bnf::{BnfOperator, BnfToken};
+use lang::{nested_comment_len, terminal_regex, unescape};
+use lex::{
+    ascii_whitespace_filter, Lexer, LexerBuilder, LexerFamily, MetaIter, SimpleErrorBytes, Token,
+};
+use std::borrow::Cow;
+use std::fmt;
 
+/// Serialized tokens deserialize back only as long as their text needs no unescaping in the
+/// serialized form, e.g. terminals with escapes do not; see `OwnedEbnfToken` for those.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub enum EbnfToken<'a> {
+    /// Terminal as written between its quotes, escapes included; see [`unescape`].
+    ///
+    /// [`unescape`]: ../fn.unescape.html
     Terminal(&'a str),
     NonTerminal(&'a str),
     Operator(EbnfOperator),
     Repeat(Side),
+    /// Number of repetitions of the following primary, e.g. `4 *` is `RepeatCount(4)`.
+    RepeatCount(u32),
     Optional(Side),
     Group(Side),
+    /// Special sequence without question marks, e.g. `? ASCII character ?` is
+    /// `Special(" ASCII character ")`.
+    Special(&'a str),
     Delimiter,
     Comment(&'a str),
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub enum EbnfOperator {
     /// Definition `"::="`
     Def,
     /// Alternative `"|"`
     Alt,
+    /// Range of characters `".."`
+    Range,
+    /// Exception `"-"`, i.e. anything the left operand matches except for the right one.
+    Except,
+    /// Concatenation `","` as in ISO EBNF, which is the same as juxtaposition.
+    Concat,
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub enum Side {
     Start,
     End,
 }
 
-fn is_whitespace(c: char) -> bool {
-    let c = c as u8;
-    return c == 0x20 // spaces (U+0020)
-        || c == 0x09 // horizontal tabs (U+0009)
-        || c == 0x0d // carriage returns (U+000D)
-        || c == 0x0a; // newlines (U+000A)
-}
-
-fn whitespace_filter(source: &str) -> &str {
-    for (i, c) in source.char_indices() {
-        if !is_whitespace(c) {
-            return &source[i..];
-        }
-    }
-    &source[source.len()..]
-}
-
 pub fn make_lexer<'a>() -> Lexer<'a, EbnfToken<'a>> {
     LexerBuilder::new()
-        .skip_whitespaces(whitespace_filter)
+        .skip_whitespaces(ascii_whitespace_filter)
         .add(r"::=", constant!(Operator(Def)))
         .add(r"\|", constant!(Operator(Alt)))
+        .add(r"\.\.", constant!(Operator(Range)))
+        .add(r"-", constant!(Operator(Except)))
+        .add(r",", constant!(Operator(Concat)))
         .add(r"<(.+?)>", |c| NonTerminal(c.get(1).unwrap().as_str()))
-        .add("\"(.*?)\"", |c| Terminal(c.get(1).unwrap().as_str()))
+        .add(&terminal_regex('"'), |c| Terminal(c.get(1).unwrap().as_str()))
+        // at most 9 digits always fit into u32
+        .add(r"([0-9]{1,9})\s*\*", |c| {
+            RepeatCount(c.get(1).unwrap().as_str().parse().unwrap())
+        })
         .add(r"\{", constant!(Repeat(Start)))
         .add(r"\}", constant!(Repeat(End)))
         .add(r"\[", constant!(Optional(Start)))
         .add(r"\]", constant!(Optional(End)))
+        // ISO comment goes before group, otherwise "(*" would be lexed as a group start
+        .add_scanner(
+            r"\(\*",
+            |s| nested_comment_len(s, "(*", "*)"),
+            |s| Comment(&s[2..s.len() - 2]),
+        )
         .add(r"\(", constant!(Group(Start)))
         .add(r"\)", constant!(Group(End)))
+        .add(r"\?(.*?)\?", |c| Special(c.get(1).unwrap().as_str()))
         .add(r";", constant!(Delimiter))
         .add(r"//([^\n]*)\n?", |c| Comment(c.get(1).unwrap().as_str()))
-        .add(r"(?s)/\*(.*?)\*/", |c| Comment(c.get(1).unwrap().as_str()))
+        .add_scanner(
+            r"/\*",
+            |s| nested_comment_len(s, "/*", "*/"),
+            |s| Comment(&s[2..s.len() - 2]),
+        )
         .build()
+        .unwrap()
 }
 
-impl<'a> Token<'a> for EbnfToken<'a> {
+/// `make_lexer` for sources of any lifetime, e.g. for `tokens_from_reader`.
+pub struct EbnfLexer;
+
+impl LexerFamily for EbnfLexer {
+    type Token<'a> = EbnfToken<'a>;
+
+    fn lexer<'a>(&self) -> Lexer<'a, EbnfToken<'a>> {
+        make_lexer()
+    }
+}
+
+impl<'a> EbnfToken<'a> {
+    /// Value of a terminal with escapes resolved (see [`unescape`]), or text of any other token
+    /// as written in the source.
+    ///
+    /// Borrows whenever there is nothing to unescape, except for a repetition count, which is
+    /// formatted as e.g. `4 *`.
+    ///
+    /// [`unescape`]: ../fn.unescape.html
+    pub fn unescaped(&self) -> Cow<'a, str> {
+        match *self {
+            Terminal(t) => unescape(t),
+            NonTerminal(t) | Special(t) | Comment(t) => Cow::Borrowed(t),
+            RepeatCount(n) => Cow::Owned(format!("{} *", n)),
+            _ => Cow::Borrowed(self.descriptor()),
+        }
+    }
+
+    /// Same as [`BnfToken::as_nonterminal`](../bnf/enum.BnfToken.html#method.as_nonterminal).
+    pub fn as_nonterminal(&self) -> Option<&'a str> {
+        match *self {
+            NonTerminal(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Same as [`BnfToken::as_terminal`](../bnf/enum.BnfToken.html#method.as_terminal).
+    pub fn as_terminal(&self) -> Option<&'a str> {
+        match *self {
+            Terminal(t) => Some(t),
+            _ => None,
+        }
+    }
+}
+
+/// Same as `EbnfToken`, but owns its text instead of borrowing it from the source.
+///
+/// Made by `EbnfToken::into_owned`, e.g. to keep tokens after the source is dropped, or to
+/// deserialize tokens with escapes.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub enum OwnedEbnfToken {
+    Terminal(String),
+    NonTerminal(String),
+    Operator(EbnfOperator),
+    Repeat(Side),
+    RepeatCount(u32),
+    Optional(Side),
+    Group(Side),
+    Special(String),
+    Delimiter,
+    Comment(String),
+}
+
+impl<'a> EbnfToken<'a> {
+    /// Copy borrowed text into an owned token.
+    pub fn into_owned(self) -> OwnedEbnfToken {
+        match self {
+            Terminal(t) => OwnedEbnfToken::Terminal(t.to_owned()),
+            NonTerminal(t) => OwnedEbnfToken::NonTerminal(t.to_owned()),
+            Operator(op) => OwnedEbnfToken::Operator(op),
+            Repeat(side) => OwnedEbnfToken::Repeat(side),
+            RepeatCount(n) => OwnedEbnfToken::RepeatCount(n),
+            Optional(side) => OwnedEbnfToken::Optional(side),
+            Group(side) => OwnedEbnfToken::Group(side),
+            Special(s) => OwnedEbnfToken::Special(s.to_owned()),
+            Delimiter => OwnedEbnfToken::Delimiter,
+            Comment(c) => OwnedEbnfToken::Comment(c.to_owned()),
+        }
+    }
+}
+
+impl OwnedEbnfToken {
+    /// Borrow text back as a regular token.
+    pub fn as_token(&self) -> EbnfToken<'_> {
+        match *self {
+            OwnedEbnfToken::Terminal(ref t) => Terminal(t),
+            OwnedEbnfToken::NonTerminal(ref t) => NonTerminal(t),
+            OwnedEbnfToken::Operator(op) => Operator(op),
+            OwnedEbnfToken::Repeat(side) => Repeat(side),
+            OwnedEbnfToken::RepeatCount(n) => RepeatCount(n),
+            OwnedEbnfToken::Optional(side) => Optional(side),
+            OwnedEbnfToken::Group(side) => Group(side),
+            OwnedEbnfToken::Special(ref s) => Special(s),
+            OwnedEbnfToken::Delimiter => Delimiter,
+            OwnedEbnfToken::Comment(ref c) => Comment(c),
+        }
+    }
+}
+
+impl fmt::Display for OwnedEbnfToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_token().fmt(f)
+    }
+}
+
+impl<'a> Token<'a> for OwnedEbnfToken {
     fn describe(&self) -> String {
+        self.to_string()
+    }
+
+    fn describe_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        write!(w, "{}", self)
+    }
+
+    fn descriptor(&self) -> &'static str {
+        self.as_token().descriptor()
+    }
+}
+
+impl<'a> fmt::Display for EbnfToken<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Terminal(t) => format!("\"{}\"", t),
-            NonTerminal(t) => format!("<{}>", t),
-            Comment(c) => format!("/* {} */\n", c),
-            _ => match *self {
+            Terminal(t) => write!(f, "\"{}\"", t),
+            NonTerminal(t) => write!(f, "<{}>", t),
+            Special(s) => write!(f, "?{}?", s),
+            Comment(c) => writeln!(f, "/* {} */", c),
+            RepeatCount(n) => write!(f, "{} *", n),
+            _ => f.write_str(match *self {
                 Operator(Def) => "::=",
                 Operator(Alt) => "|",
+                Operator(Range) => "..",
+                Operator(Except) => "-",
+                Operator(Concat) => ",",
                 Repeat(Start) => "{",
                 Repeat(End) => "}",
                 Optional(Start) => "[",
@@ -93,7 +251,24 @@ impl<'a> Token<'a> for EbnfToken<'a> {
                 Group(End) => ")",
                 Delimiter => ";",
                 _ => unreachable!(),
-            }.to_string(),
+            }),
+        }
+    }
+}
+
+impl<'a> Token<'a> for EbnfToken<'a> {
+    fn describe(&self) -> String {
+        self.to_string()
+    }
+
+    fn describe_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        write!(w, "{}", self)
+    }
+
+    fn identifier(&self) -> Option<&str> {
+        match *self {
+            NonTerminal(name) => Some(name),
+            _ => None,
         }
     }
 
@@ -101,8 +276,13 @@ impl<'a> Token<'a> for EbnfToken<'a> {
         match *self {
             Terminal(..) => "Terminal",
             NonTerminal(..) => "NonTerminal",
+            RepeatCount(..) => "RepeatCount",
+            Special(..) => "Special",
             Operator(Def) => "::=",
             Operator(Alt) => "|",
+            Operator(Range) => "..",
+            Operator(Except) => "-",
+            Operator(Concat) => ",",
             Repeat(Start) => "{",
             Repeat(End) => "}",
             Optional(Start) => "[",
@@ -126,6 +306,260 @@ where
     DropComments { inner: tokens }
 }
 
+/// Check that repetitions, options and groups are balanced, e.g. before `to_bnf`.
+///
+/// # Returns
+///
+/// Error at the first closing bracket which does not match the last open one, or at the first
+/// bracket which is left open. Lexer errors are returned as they are.
+pub fn check_balance<'a, I>(tokens: I) -> Result<(), SimpleErrorBytes>
+where
+    I: MetaIter<'a, EbnfToken<'a>>,
+{
+    let mut open = Vec::new();
+    for meta in tokens {
+        let meta = meta?;
+        match meta.token {
+            Repeat(Start) | Optional(Start) | Group(Start) => open.push(meta),
+            Repeat(End) | Optional(End) | Group(End) => {
+                let start = match open.pop() {
+                    Some(start) => start,
+                    None => {
+                        let description = format!("Unmatched {}.", meta.token);
+                        return Err(SimpleErrorBytes::from(meta).description(description));
+                    }
+                };
+                let expected = match start.token {
+                    Repeat(Start) => Repeat(End),
+                    Optional(Start) => Optional(End),
+                    _ => Group(End),
+                };
+                if meta.token != expected {
+                    let description = format!(
+                        "Expected {} to close {} at line {}, found {}.",
+                        expected,
+                        start.token,
+                        start.line(),
+                        meta.token,
+                    );
+                    return Err(SimpleErrorBytes::from(meta).description(description));
+                }
+            }
+            _ => {}
+        }
+    }
+    match open.into_iter().next() {
+        Some(start) => {
+            let description = format!("Unclosed {}.", start.token);
+            Err(SimpleErrorBytes::from(start).description(description))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Lower EBNF tokens into equivalent BNF ones.
+///
+/// Every repetition, option and group is replaced with a reference to a helper non-terminal
+/// named after the enclosing rule, e.g. `<A__rep0>`, `<A__opt0>` or `<A__grp0>`, which is then
+/// defined right after that rule:
+///
+/// ```bnf
+/// <A__rep0> ::= X <A__rep0> | "" ;
+/// <A__opt0> ::= X | "" ;
+/// <A__grp0> ::= X ;
+/// ```
+///
+/// where empty terminal `""` stands for epsilon. Repetition counts are unrolled, and comments
+/// are dropped. Names of helpers never clash with non-terminals already present in `tokens`.
+///
+/// Generated names are stored in `names`, which the returned tokens borrow from.
+///
+/// # Panics
+///
+/// Panics if tokens do not form well-formed rules (e.g. brackets are unbalanced), or contain
+/// ranges, exceptions or special sequences which have no BNF equivalent.
+pub fn to_bnf<'a, I>(tokens: I, names: &'a mut Vec<String>) -> Vec<BnfToken<'a>>
+where
+    I: IntoIterator<Item = EbnfToken<'a>>,
+{
+    // explicit concatenation is the same as juxtaposition
+    let tokens: Vec<_> = tokens
+        .into_iter()
+        .filter(|t| !matches!(*t, Comment(_) | Operator(Concat)))
+        .collect();
+
+    let mut lowering = Lowering {
+        taken: tokens
+            .iter()
+            .filter_map(|t| match *t {
+                NonTerminal(name) => Some(name),
+                _ => None,
+            }).collect(),
+        names,
+        rules: Vec::new(),
+    };
+    let mut lowered = Vec::new();
+
+    for rule in tokens.split(|t| *t == Delimiter).filter(|r| !r.is_empty()) {
+        let name = match rule[..] {
+            [NonTerminal(name), Operator(Def), ..] => name,
+            _ => panic!("rule must start with a non-terminal and a definition operator"),
+        };
+        let body = lowering.lower(name, &rule[2..], &mut [0; 3]);
+        lowered.push(Lowered::Token(BnfToken::NonTerminal(name)));
+        lowered.push(Lowered::Token(BnfToken::Operator(BnfOperator::Def)));
+        lowered.extend(body);
+        lowered.push(Lowered::Token(BnfToken::Delimiter));
+        lowered.append(&mut lowering.rules);
+    }
+
+    let names: &'a Vec<String> = lowering.names;
+    lowered
+        .into_iter()
+        .map(|l| match l {
+            Lowered::Token(t) => t,
+            Lowered::Aux(i) => BnfToken::NonTerminal(&names[i]),
+        }).collect()
+}
+
+/// BNF token which may refer to a generated name not yet borrowable.
+#[derive(Copy, Clone)]
+enum Lowered<'a> {
+    Token(BnfToken<'a>),
+    /// Index of the helper name in `Lowering::names`.
+    Aux(usize),
+}
+
+struct Lowering<'a, 'n> {
+    taken: Vec<&'a str>,
+    names: &'n mut Vec<String>,
+    /// Helper rules generated so far for the current rule.
+    rules: Vec<Lowered<'a>>,
+}
+
+impl<'a, 'n> Lowering<'a, 'n> {
+    /// Lower definition list of the rule `name`. Counters of helpers are per-kind:
+    /// repetitions, options and groups.
+    fn lower(
+        &mut self,
+        name: &str,
+        tokens: &[EbnfToken<'a>],
+        counters: &mut [usize; 3],
+    ) -> Vec<Lowered<'a>> {
+        let mut lowered = Vec::new();
+        let mut rest = tokens;
+        while !rest.is_empty() {
+            if rest[0] == Operator(Alt) {
+                lowered.push(Lowered::Token(BnfToken::Operator(BnfOperator::Alt)));
+                rest = &rest[1..];
+            } else {
+                rest = self.lower_primary(name, rest, counters, &mut lowered);
+            }
+        }
+        lowered
+    }
+
+    /// Lower the first primary of `tokens` into `out`, and return remaining tokens.
+    fn lower_primary<'t>(
+        &mut self,
+        name: &str,
+        tokens: &'t [EbnfToken<'a>],
+        counters: &mut [usize; 3],
+        out: &mut Vec<Lowered<'a>>,
+    ) -> &'t [EbnfToken<'a>] {
+        let (kind, end) = match tokens[0] {
+            Terminal(t) => {
+                out.push(Lowered::Token(BnfToken::Terminal(t)));
+                return &tokens[1..];
+            }
+            NonTerminal(t) => {
+                out.push(Lowered::Token(BnfToken::NonTerminal(t)));
+                return &tokens[1..];
+            }
+            RepeatCount(n) => {
+                let mut primary = Vec::new();
+                let rest = self.lower_primary(name, &tokens[1..], counters, &mut primary);
+                if n == 0 {
+                    out.push(Lowered::Token(BnfToken::Terminal("")));
+                }
+                for _ in 0..n {
+                    out.extend_from_slice(&primary);
+                }
+                return rest;
+            }
+            Repeat(Start) => (0, Repeat(End)),
+            Optional(Start) => (1, Optional(End)),
+            Group(Start) => (2, Group(End)),
+            t => panic!("unexpected {} in a rule definition", t),
+        };
+
+        let close = matching(tokens, end);
+        let inner = self.lower(name, &tokens[1..close], counters);
+
+        let aux = self.allocate_name(name, kind, counters);
+        let mut rule = Vec::new();
+        rule.push(Lowered::Aux(aux));
+        rule.push(Lowered::Token(BnfToken::Operator(BnfOperator::Def)));
+        for l in inner {
+            // only alternatives are left at top level, every nesting is already a helper
+            let alt = matches!(l, Lowered::Token(BnfToken::Operator(BnfOperator::Alt)));
+            if alt && kind == 0 {
+                rule.push(Lowered::Aux(aux));
+            }
+            rule.push(l);
+        }
+        match kind {
+            0 => {
+                rule.push(Lowered::Aux(aux));
+                rule.push(Lowered::Token(BnfToken::Operator(BnfOperator::Alt)));
+                rule.push(Lowered::Token(BnfToken::Terminal("")));
+            }
+            1 => {
+                rule.push(Lowered::Token(BnfToken::Operator(BnfOperator::Alt)));
+                rule.push(Lowered::Token(BnfToken::Terminal("")));
+            }
+            _ => {}
+        }
+        rule.push(Lowered::Token(BnfToken::Delimiter));
+        self.rules.append(&mut rule);
+
+        out.push(Lowered::Aux(aux));
+        &tokens[close + 1..]
+    }
+
+    /// Generate a name which is neither taken by the source nor generated before.
+    fn allocate_name(&mut self, name: &str, kind: usize, counters: &mut [usize; 3]) -> usize {
+        let suffix = ["rep", "opt", "grp"][kind];
+        loop {
+            let candidate = format!("{}__{}{}", name, suffix, counters[kind]);
+            counters[kind] += 1;
+            if !self.taken.contains(&&*candidate) && !self.names.contains(&candidate) {
+                self.names.push(candidate);
+                return self.names.len() - 1;
+            }
+        }
+    }
+}
+
+/// Index of the `end` token which closes the bracket at `tokens[0]`.
+fn matching(tokens: &[EbnfToken], end: EbnfToken) -> usize {
+    let mut depth = 0;
+    for (i, t) in tokens.iter().enumerate() {
+        match *t {
+            Repeat(Start) | Optional(Start) | Group(Start) => depth += 1,
+            Repeat(End) | Optional(End) | Group(End) => {
+                depth -= 1;
+                if depth == 0 {
+                    assert_eq!(*t, end, "unbalanced brackets");
+                    return i;
+                }
+            }
+            _ => {}
+        }
+    }
+    panic!("unbalanced brackets")
+}
+
 mod impls {
     use super::*;
     use lex::{MetaResult, TokenMeta};
@@ -153,7 +587,7 @@ mod impls {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use lex::TokensExt;
+    use lex::{Location, TokensExt};
 
     const SOURCE: &str = r#"
         <A> // x y z
@@ -203,4 +637,437 @@ mod tests {
             .collect();
         assert_eq!(tokens, expected);
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use serde_json;
+
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(SOURCE, FILENAME.into())
+            .into_raw()
+            .collect();
+        let json = serde_json::to_string(&tokens).unwrap();
+        let back: Vec<EbnfToken> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(back, tokens);
+        assert_eq!(back, TOKENS);
+
+        // escapes are unescaped by the deserializer, so only owned tokens can keep them
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(r#"<A> ::= "a\"b" ;"#, FILENAME.into())
+            .into_raw()
+            .map(EbnfToken::into_owned)
+            .collect();
+        let json = serde_json::to_string(&tokens).unwrap();
+        assert!(serde_json::from_str::<Vec<EbnfToken>>(&json).is_err());
+        let back: Vec<OwnedEbnfToken> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(back, tokens);
+        assert_eq!(back[2].as_token(), Terminal(r#"a\"b"#));
+    }
+
+    #[test]
+    fn test_repeat_count() {
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens("<Quad> ::= 4 * <Digit> ; <Pair> ::= {2*<Digit>}", FILENAME.into())
+            .into_raw()
+            .collect();
+
+        assert_eq!(
+            tokens,
+            [
+                NonTerminal("Quad"),
+                Operator(Def),
+                RepeatCount(4),
+                NonTerminal("Digit"),
+                Delimiter,
+                NonTerminal("Pair"),
+                Operator(Def),
+                Repeat(Start),
+                RepeatCount(2),
+                NonTerminal("Digit"),
+                Repeat(End),
+            ]
+        );
+        assert_eq!(tokens[2].unescaped(), "4 *");
+    }
+
+    #[test]
+    fn test_range() {
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(r#"<Digit> ::= "0" .. "9" ;"#, FILENAME.into())
+            .into_raw()
+            .collect();
+
+        assert_eq!(
+            tokens,
+            [
+                NonTerminal("Digit"),
+                Operator(Def),
+                Terminal("0"),
+                Operator(Range),
+                Terminal("9"),
+                Delimiter,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_except() {
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(r#"<A> ::= <Any> - "x" ;"#, FILENAME.into())
+            .into_raw()
+            .collect();
+
+        assert_eq!(
+            tokens,
+            [
+                NonTerminal("A"),
+                Operator(Def),
+                NonTerminal("Any"),
+                Operator(Except),
+                Terminal("x"),
+                Delimiter,
+            ]
+        );
+
+        // dashes inside of non-terminals and terminals are not operators
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(r#"<a-b> ::= "-" ;"#, FILENAME.into())
+            .into_raw()
+            .collect();
+        assert_eq!(tokens, [NonTerminal("a-b"), Operator(Def), Terminal("-"), Delimiter]);
+    }
+
+    #[test]
+    fn test_concat() {
+        let source = r#"<A> ::= "x" , "y" ;"#;
+        let tokens = make_lexer().into_tokens(source, FILENAME.into()).tokens_only();
+        assert_eq!(
+            tokens,
+            [
+                NonTerminal("A"),
+                Operator(Def),
+                Terminal("x"),
+                Operator(Concat),
+                Terminal("y"),
+                Delimiter,
+            ]
+        );
+        assert_eq!(tokens[3].descriptor(), ",");
+        assert_eq!(tokens[3].describe(), ",");
+
+        // commas inside of terminals are not operators
+        let source = r#"<A> ::= "," "y" ;"#;
+        let tokens = make_lexer().into_tokens(source, FILENAME.into()).tokens_only();
+        assert_eq!(tokens[2..4], [Terminal(","), Terminal("y")]);
+    }
+
+    #[test]
+    fn test_check_balance() {
+        let balance = |source| check_balance(make_lexer().into_tokens(source, FILENAME.into()));
+
+        assert!(balance(SOURCE).is_ok());
+        assert!(balance(r#"<A> ::= { [ ( "x" ) ] } ;"#).is_ok());
+
+        let error = balance(r#"<A> ::= ( "x" ;"#).unwrap_err();
+        assert_eq!(error.span.range(), 8..9);
+        assert_eq!(error.description.unwrap(), "Unclosed (.");
+
+        let error = balance(r#"<A> ::= [ "x" ) ;"#).unwrap_err();
+        assert_eq!(error.span.range(), 14..15);
+        assert_eq!(error.description.unwrap(), "Expected ] to close [ at line 1, found ).");
+
+        let error = balance(r#"<A> ::= "x" } ;"#).unwrap_err();
+        assert_eq!(error.span.range(), 12..13);
+        assert_eq!(error.description.unwrap(), "Unmatched }.");
+
+        // lexer errors come first
+        let error = balance(r#"<A> ::= ( @"#).unwrap_err();
+        assert_eq!(error.span.range(), 10..11);
+    }
+
+    #[test]
+    fn test_as_nonterminal() {
+        let tokens = make_lexer().into_tokens(SOURCE, FILENAME.into()).tokens_only();
+        let names: Vec<_> = tokens.iter().filter_map(EbnfToken::as_nonterminal).collect();
+        assert_eq!(names, ["A", "B", "D"]);
+        let terminals: Vec<_> = tokens.iter().filter_map(EbnfToken::as_terminal).collect();
+        assert_eq!(terminals, ["c"]);
+    }
+
+    #[test]
+    fn test_only() {
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(SOURCE, FILENAME.into())
+            .only("NonTerminal")
+            .into_raw()
+            .collect();
+
+        assert_eq!(tokens, [NonTerminal("A"), NonTerminal("B"), NonTerminal("D")]);
+
+        let mut tokens = make_lexer()
+            .into_tokens("<A> ::= ! <B>", FILENAME.into())
+            .only("NonTerminal");
+        assert!(tokens.next().unwrap().is_ok());
+        assert!(tokens.next().unwrap().is_err());
+        assert!(tokens.next().is_none());
+    }
+
+    #[test]
+    fn test_unescaped() {
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(r#""a\tb" "plain" <A> ::="#, FILENAME.into())
+            .into_raw()
+            .collect();
+
+        match tokens[0].unescaped() {
+            Cow::Owned(ref s) => assert_eq!(s, "a\tb"),
+            Cow::Borrowed(s) => panic!("expected owned value, got {:?}", s),
+        }
+        match tokens[1].unescaped() {
+            Cow::Borrowed(s) => assert_eq!(s, "plain"),
+            Cow::Owned(s) => panic!("expected borrowed value, got {:?}", s),
+        }
+        assert_eq!(tokens[2].unescaped(), "A");
+        assert_eq!(tokens[3].unescaped(), "::=");
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(format!("{}", NonTerminal("X")), "<X>");
+        assert_eq!(format!("{}", Terminal("x")), "\"x\"");
+        assert_eq!(format!("{}", Operator(Def)), "::=");
+    }
+
+    #[test]
+    fn test_describe_all() {
+        let described = drop_comments(make_lexer().into_tokens(SOURCE, FILENAME.into()))
+            .describe_all();
+
+        assert_eq!(described, r#"<A> ::= ( <B> | { "c" } ) [ <D> ] ;"#);
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "// header\n<A> ::= \"a\" // end of rule\n ; // done";
+        let rendered = make_lexer().into_tokens(source, FILENAME.into()).render();
+        assert_eq!(
+            rendered,
+            "/*  header */\n<A> ::= \"a\" /*  end of rule */\n; /*  done */"
+        );
+
+        // describe_all keeps the newline of every comment
+        let described = make_lexer().into_tokens(source, FILENAME.into()).describe_all();
+        assert!(described.ends_with("/*  done */\n"));
+    }
+
+    #[test]
+    fn test_escaped_terminal() {
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(r#""a\"b" "\\""#, FILENAME.into())
+            .into_raw()
+            .collect();
+
+        assert_eq!(tokens, [Terminal(r#"a\"b"#), Terminal(r#"\\"#)]);
+        if let Terminal(t) = tokens[0] {
+            assert_eq!(unescape(t), "a\"b");
+        }
+        if let Terminal(t) = tokens[1] {
+            assert_eq!(unescape(t), "\\");
+        }
+    }
+
+    #[test]
+    fn test_iso_comment() {
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(r#"<A> (* note *) ::= "x" (* multi
+                line *) ( "y" ) ;"#, FILENAME.into())
+            .into_raw()
+            .collect();
+
+        assert_eq!(
+            tokens,
+            [
+                NonTerminal("A"),
+                Comment(" note "),
+                Operator(Def),
+                Terminal("x"),
+                Comment(" multi\n                line "),
+                Group(Start),
+                Terminal("y"),
+                Group(End),
+                Delimiter,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_special() {
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens("<Char> ::= ? any visible character ? ;", FILENAME.into())
+            .into_raw()
+            .collect();
+
+        assert_eq!(
+            tokens,
+            [
+                NonTerminal("Char"),
+                Operator(Def),
+                Special(" any visible character "),
+                Delimiter,
+            ]
+        );
+        assert_eq!(tokens[2].descriptor(), "Special");
+        assert_eq!(tokens[2].describe(), "? any visible character ?");
+        assert_eq!(tokens[2].unescaped(), " any visible character ");
+    }
+
+    #[test]
+    fn test_lossless() {
+        use lex::Trivia;
+
+        let tokens: Vec<_> = make_lexer()
+            .lossless_tokens(SOURCE, FILENAME.into())
+            .map(Result::unwrap)
+            .collect();
+        let joined: String = tokens.iter().map(|meta| &SOURCE[meta.span.range()]).collect();
+        assert_eq!(joined, SOURCE);
+
+        let raw: Vec<_> = tokens
+            .iter()
+            .filter_map(|meta| match meta.token {
+                Trivia::Token(t) => Some(t),
+                Trivia::Trivia(_) => None,
+            }).collect();
+        assert_eq!(raw, TOKENS);
+        assert_eq!(tokens[0].token, Trivia::Trivia("\n        "));
+        assert_eq!(tokens[0].line(), 1);
+        assert_eq!(tokens[1].line(), 2);
+        assert_eq!(tokens[1].column(), 9);
+
+        // byte order mark is not lost either
+        let with_bom = format!("\u{FEFF}{}", SOURCE);
+        let joined: String = make_lexer()
+            .lossless_tokens(&with_bom, FILENAME.into())
+            .map(|meta| &with_bom[meta.unwrap().span.range()])
+            .collect();
+        assert_eq!(joined, with_bom);
+    }
+
+    #[test]
+    fn test_nested_comments() {
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens("<A> /* a /* b */ c */ (* d (* e *) *) ::=", FILENAME.into())
+            .into_raw()
+            .collect();
+
+        assert_eq!(
+            tokens,
+            [
+                NonTerminal("A"),
+                Comment(" a /* b */ c "),
+                Comment(" d (* e *) "),
+                Operator(Def),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let lexer = make_lexer();
+        assert!(lexer.validate(SOURCE, FILENAME.into()).is_ok());
+
+        let error = lexer.validate("<A> ::=\n    <B> @ <C> ;", FILENAME.into()).unwrap_err();
+        assert_eq!(error.span.start, Location::new(2, 9, 16));
+        assert_eq!(error.filename, FILENAME);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = make_lexer().into_tokens(SOURCE, FILENAME.into()).histogram();
+
+        assert_eq!(histogram["NonTerminal"], 3);
+        assert_eq!(histogram["Terminal"], 1);
+        assert_eq!(histogram["Comment"], 2);
+        assert_eq!(histogram.values().sum::<usize>(), TOKENS.len());
+        assert!(!histogram.contains_key("<error>"));
+
+        let histogram = make_lexer().into_tokens("<A> ::= !", FILENAME.into()).histogram();
+        assert_eq!(histogram["<error>"], 1);
+    }
+
+    #[test]
+    fn test_to_bnf() {
+        use lang::bnf::BnfToken as Bnf;
+
+        let tokens = make_lexer()
+            .into_tokens(r#"<A> ::= {"x"} ; // trailing comment"#, FILENAME.into())
+            .into_raw();
+        let mut names = Vec::new();
+        let lowered = to_bnf(tokens, &mut names);
+
+        assert_eq!(
+            lowered,
+            [
+                Bnf::NonTerminal("A"),
+                Bnf::Operator(BnfOperator::Def),
+                Bnf::NonTerminal("A__rep0"),
+                Bnf::Delimiter,
+                Bnf::NonTerminal("A__rep0"),
+                Bnf::Operator(BnfOperator::Def),
+                Bnf::Terminal("x"),
+                Bnf::NonTerminal("A__rep0"),
+                Bnf::Operator(BnfOperator::Alt),
+                Bnf::Terminal(""),
+                Bnf::Delimiter,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_bnf_concat() {
+        use lang::bnf::BnfToken as Bnf;
+
+        let tokens = make_lexer()
+            .into_tokens(r#"<A> ::= "x" , "y" ;"#, FILENAME.into())
+            .into_raw();
+        let mut names = Vec::new();
+        let lowered = to_bnf(tokens, &mut names);
+
+        assert_eq!(
+            lowered,
+            [
+                Bnf::NonTerminal("A"),
+                Bnf::Operator(BnfOperator::Def),
+                Bnf::Terminal("x"),
+                Bnf::Terminal("y"),
+                Bnf::Delimiter,
+            ]
+        );
+        assert!(names.is_empty());
+    }
+
+    #[test]
+    fn test_to_bnf_nested() {
+        let source = r#"
+            <A> ::= ["a" | 2 * <A__opt0>] ("b" | {"c" | "d"}) ;
+            <A__opt0> ::= "e"
+        "#;
+        let tokens = make_lexer().into_tokens(source, FILENAME.into()).into_raw();
+        let mut names = Vec::new();
+        let lowered = to_bnf(tokens, &mut names);
+
+        let described: Vec<_> = lowered.iter().map(|t| t.to_string()).collect();
+        assert_eq!(
+            described.join(" "),
+            "<A> ::= <A__opt1> <A__grp0> ; \
+             <A__opt1> ::= \"a\" | <A__opt0> <A__opt0> | \"\" ; \
+             <A__rep0> ::= \"c\" <A__rep0> | \"d\" <A__rep0> | \"\" ; \
+             <A__grp0> ::= \"b\" | <A__rep0> ; \
+             <A__opt0> ::= \"e\" ;"
+        );
+        assert_eq!(names, ["A__opt1", "A__rep0", "A__grp0"]);
+    }
 }
@@ -1,7 +1,17 @@
 pub use self::GoToken::*;
+use lang::nested_comment_len;
 #[allow(unused)]
-use lex::{Lexer, LexerBuilder, Location, MetaResult, Span, Token, TokenMeta, TokensExt};
-
+use lex::{
+    ascii_whitespace_filter, Category, Error, Lexer, LexerBuilder, Location, MetaResult, Span,
+    Token, TokenMeta, TokensExt,
+};
+use std::fmt;
+use std::iter::Peekable;
+use std::thread;
+
+/// Serialized tokens deserialize back only as long as their text needs no unescaping in the
+/// serialized form, e.g. string literals with escapes do not; see `OwnedGoToken` for those.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub enum GoToken<'a> {
     /// identifiers
@@ -12,11 +22,14 @@ pub enum GoToken<'a> {
     Operator(GoOperator),
     /// Literal values like strings and numbers.
     Literal(GoLiteral<'a>),
-    /// Single-line and multi-line comments
+    /// Single-line and multi-line comments as written, markers included, e.g. `// hi` or
+    /// `/* hi */`, so that they are described back exactly. Newline after a line comment is
+    /// not included.
     Comment(&'a str),
 }
 
 /// Go programming language keywords
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub enum GoKeyword {
     Break,
@@ -46,6 +59,7 @@ pub enum GoKeyword {
     Var,
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub enum GoOperator {
     Add,
@@ -105,6 +119,7 @@ pub enum GoOperator {
     Colon,
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub enum GoLiteral<'a> {
     /// ```raw_string_lit         = "`" { unicode_char | newline } "`" .```
@@ -132,25 +147,34 @@ pub enum GoLiteral<'a> {
     Rune(&'a str),
 }
 
-fn is_whitespace(c: char) -> bool {
-    let c = c as u8;
-    return c == 0x20 // spaces (U+0020)
-        || c == 0x09 // horizontal tabs (U+0009)
-        || c == 0x0d // carriage returns (U+000D)
-        || c == 0x0a; // newlines (U+000A)
+pub fn make_lexer<'a>() -> Lexer<'a, GoToken<'a>> {
+    lexer_builder().build().unwrap()
 }
 
-fn whitespace_filter(source: &str) -> &str {
-    for (i, c) in source.char_indices() {
-        if !is_whitespace(c) {
-            return &source[i..];
-        }
-    }
-    &source[source.len()..]
+/// Rules of `make_lexer`, for further tuning of the lexer.
+pub fn lexer_builder<'a>() -> LexerBuilder<'a, GoToken<'a>> {
+    lexer_builder_with(false)
 }
 
-pub fn make_lexer<'a>() -> Lexer<'a, GoToken<'a>> {
-    let rune = r#"(?x)
+/// Same as `lexer_builder`, but block comments may nest (e.g. `/* a /* b */ c */`) if
+/// `nested_comments` is set. Go itself does not allow that, so it is off by default.
+pub fn lexer_builder_with<'a>(nested_comments: bool) -> LexerBuilder<'a, GoToken<'a>> {
+    // Escapes below are restricted to valid Unicode code points (up to U+10FFFF, except
+    // surrogate halves U+D800 to U+DFFF) and to byte values (up to \377).
+    let code_point_4 = r"(?: [0-9a-cA-Ce-fE-F] [[:xdigit:]]{3} | [dD] [0-7] [[:xdigit:]]{2} )";
+    // little_u_value = `\` "u" hex_digit hex_digit hex_digit hex_digit .
+    let little_u_value = format!(r"\\u {}", code_point_4);
+    // big_u_value    = `\` "U" hex_digit hex_digit hex_digit hex_digit
+    //                           hex_digit hex_digit hex_digit hex_digit .
+    let big_u_value = format!(
+        r"\\U 00 (?: 10 [[:xdigit:]]{{4}} | 0 [1-9a-fA-F] [[:xdigit:]]{{4}} | 00 {} )",
+        code_point_4,
+    );
+    // octal_byte_value = `\` octal_digit octal_digit octal_digit .
+    let octal_byte_value = r"\\ [0-3] [0-7]{2}";
+
+    let rune = format!(
+        r#"(?x)
         ' # open quote
         ( # unicode_value = unicode_char | little_u_value | big_u_value | escaped_char
 
@@ -158,21 +182,26 @@ pub fn make_lexer<'a>() -> Lexer<'a, GoToken<'a>> {
               # Note: also except close quote and backslash [as it must be followed by other character(s)]
                 [^\\\n']
             | # little_u_value
-                \\u [[:xdigit:]]{4}
+                {little_u}
             | # big_u_value
-                \\U [[:xdigit:]]{8}
+                {big_u}
             | # escaped_char
-                \\   [abfnrtv\\'"]
+              # Note: `\"` is legal in string literals only
+                \\   [abfnrtv\\']
 
         | # byte value = octal_byte_value | hex_byte_value
 
               # octal_byte_value
-                \\   [0-7]{3}
+                {octal}
             | # hex_byte_value
-                \\x [[:xdigit:]]{2}
+                \\x [[:xdigit:]]{{2}}
         )
         ' # close quote
-    "#;
+        "#,
+        little_u = little_u_value,
+        big_u = big_u_value,
+        octal = octal_byte_value,
+    );
 
     // raw_string_lit         = "`" { unicode_char | newline } "`" .
     let raw_string = r#"(?x)
@@ -188,7 +217,8 @@ pub fn make_lexer<'a>() -> Lexer<'a, GoToken<'a>> {
     "#;
 
     // interpreted_string_lit = `"` { unicode_value | byte_value } `"` .
-    let interpreted_string = r#"(?x)
+    let interpreted_string = format!(
+        r#"(?x)
         " # open quote
         ( # group 1
             (?: # unicode_value = unicode_char | little_u_value | big_u_value | escaped_char
@@ -197,90 +227,165 @@ pub fn make_lexer<'a>() -> Lexer<'a, GoToken<'a>> {
                   # Note: also except close quote and backslash [as it must be followed by other character(s)]
                     [^\\\n"]
                 | # little_u_value
-                    \\u [[:xdigit:]]{4}
+                    {little_u}
                 | # big_u_value
-                    \\U [[:xdigit:]]{8}
+                    {big_u}
                 | # escaped_char
-                    \\   [abfnrtv\\'"]
+                  # Note: `\'` is legal in rune literals only
+                    \\   [abfnrtv\\"]
 
             |   # byte value = octal_byte_value | hex_byte_value
 
                   # octal_byte_value
-                    \\   [0-7]{3}
+                    {octal}
                 | # hex_byte_value
-                    \\x [[:xdigit:]]{2}
+                    \\x [[:xdigit:]]{{2}}
             )*
         ) # end group 1
         " # close quote
+        "#,
+        little_u = little_u_value,
+        big_u = big_u_value,
+        octal = octal_byte_value,
+    );
+
+    // decimal_digits   = decimal_digit { [ "_" ] decimal_digit } .
+    let decimal_digits = r"[[:digit:]](?:_?[[:digit:]])*";
+    // hex_digits       = hex_digit { [ "_" ] hex_digit } .
+    let hex_digits = r"[[:xdigit:]](?:_?[[:xdigit:]])*";
+
+    // decimal_float_lit = decimal_digits "." [ decimal_digits ] [ decimal_exponent ] |
+    //                     decimal_digits decimal_exponent |
+    //                     "." decimal_digits [ decimal_exponent ] .
+    // decimal_exponent  = ( "e" | "E" ) [ "+" | "-" ] decimal_digits .
+    let decimal_float = format!(
+        r"(?x)
+        (?:
+              {d} \. (?: {d} )? (?: {e} )?
+            | {d} {e}
+            | \. {d} (?: {e} )?
+        )
+        ",
+        d = decimal_digits,
+        e = format!(r"[eE] [+-]? {}", decimal_digits),
+    );
+
+    // hex_float_lit     = "0" ( "x" | "X" ) hex_mantissa hex_exponent .
+    // hex_mantissa      = [ "_" ] hex_digits "." [ hex_digits ] |
+    //                     [ "_" ] hex_digits |
+    //                     "." hex_digits .
+    // hex_exponent      = ( "p" | "P" ) [ "+" | "-" ] decimal_digits .
+    let hex_float = format!(
+        r"(?x)
+        0 [xX]
+        (?:
+              _? {h} \. (?: {h} )?
+            | _? {h}
+            | \. {h}
+        )
+        [pP] [+-]? {d}
+        ",
+        d = decimal_digits,
+        h = hex_digits,
+    );
+
+    // imaginary_lit = (decimal_digits | int_lit | float_lit) "i" .
+    // Note: decimal_digits also cover decimal and legacy octal integers.
+    let imaginary = format!(
+        r"(?x)
+        (?:
+              {hex_float}
+            | {decimal_float}
+            | 0 [xX] _? {h}
+            | 0 [bB] _? [01] (?: _? [01] )*
+            | 0 [oO] _? [0-7] (?: _? [0-7] )*
+            | {d}
+        )
+        i
+        ",
+        hex_float = hex_float,
+        decimal_float = decimal_float,
+        d = decimal_digits,
+        h = hex_digits,
+    );
+
+    let builder = LexerBuilder::new()
+        // Newlines are still significant for semicolon insertion, so lexer reports them
+        // via `TokenMeta::newline` of the following token.
+        .skip_whitespaces(ascii_whitespace_filter)
+        // ...
+        .add(r"//[^\n]*", |c| Comment(c.get(0).unwrap().as_str()));
+    let builder = if nested_comments {
+        builder.add_scanner(
+            r"/\*",
+            |s| nested_comment_len(s, "/*", "*/"),
+            Comment,
+        )
+    } else {
+        builder.add(r"(?s)/\*.*?\*/", |c| Comment(c.get(0).unwrap().as_str()))
+    };
 
-    "#;
-
-    LexerBuilder::new()
-        .skip_whitespaces(whitespace_filter)
+    builder
         // ...
-        .add(r"//([^\n]*)\n?", |c| Comment(c.get(1).unwrap().as_str()))
-        .add(r"(?s)/\*(.*?)\*/", |c| Comment(c.get(1).unwrap().as_str()))
+        .add_keyword("break", Keyword(GoKeyword::Break))
+        .add_keyword("case", Keyword(GoKeyword::Case))
+        .add_keyword("chan", Keyword(GoKeyword::Chan))
+        .add_keyword("const", Keyword(GoKeyword::Const))
+        .add_keyword("continue", Keyword(GoKeyword::Continue))
+        .add_keyword("default", Keyword(GoKeyword::Default))
+        .add_keyword("defer", Keyword(GoKeyword::Defer))
+        .add_keyword("else", Keyword(GoKeyword::Else))
+        .add_keyword("fallthrough", Keyword(GoKeyword::Fallthrough))
+        .add_keyword("for", Keyword(GoKeyword::For))
+        .add_keyword("func", Keyword(GoKeyword::Func))
+        .add_keyword("go", Keyword(GoKeyword::Go))
+        .add_keyword("goto", Keyword(GoKeyword::Goto))
+        .add_keyword("if", Keyword(GoKeyword::If))
+        .add_keyword("import", Keyword(GoKeyword::Import))
+        .add_keyword("interface", Keyword(GoKeyword::Interface))
+        .add_keyword("map", Keyword(GoKeyword::Map))
+        .add_keyword("package", Keyword(GoKeyword::Package))
+        .add_keyword("range", Keyword(GoKeyword::Range))
+        .add_keyword("return", Keyword(GoKeyword::Return))
+        .add_keyword("select", Keyword(GoKeyword::Select))
+        .add_keyword("struct", Keyword(GoKeyword::Struct))
+        .add_keyword("switch", Keyword(GoKeyword::Switch))
+        .add_keyword("type", Keyword(GoKeyword::Type))
+        .add_keyword("var", Keyword(GoKeyword::Var))
         // ...
-        .add(r"break\b", constant!(Keyword(GoKeyword::Break)))
-        .add(r"case\b", constant!(Keyword(GoKeyword::Case)))
-        .add(r"defer\b", constant!(Keyword(GoKeyword::Chan)))
-        .add(r"else\b", constant!(Keyword(GoKeyword::Const)))
-        .add(r"continue\b", constant!(Keyword(GoKeyword::Continue)))
-        .add(r"default\b", constant!(Keyword(GoKeyword::Default)))
-        .add(r"defer\b", constant!(Keyword(GoKeyword::Defer)))
-        .add(r"else\b", constant!(Keyword(GoKeyword::Else)))
-        .add(r"fallthroug\b", constant!(Keyword(GoKeyword::Fallthrough)))
-        .add(r"for\b", constant!(Keyword(GoKeyword::For)))
-        .add(r"func\b", constant!(Keyword(GoKeyword::Func)))
-        .add(r"go\b", constant!(Keyword(GoKeyword::Go)))
-        .add(r"goto\b", constant!(Keyword(GoKeyword::Goto)))
-        .add(r"if\b", constant!(Keyword(GoKeyword::If)))
-        .add(r"import\b", constant!(Keyword(GoKeyword::Import)))
-        .add(r"interface\b", constant!(Keyword(GoKeyword::Interface)))
-        .add(r"map\b", constant!(Keyword(GoKeyword::Map)))
-        .add(r"package\b", constant!(Keyword(GoKeyword::Package)))
-        .add(r"range\b", constant!(Keyword(GoKeyword::Range)))
-        .add(r"return\b", constant!(Keyword(GoKeyword::Return)))
-        .add(r"select\b", constant!(Keyword(GoKeyword::Select)))
-        .add(r"struct\b", constant!(Keyword(GoKeyword::Struct)))
-        .add(r"switch\b", constant!(Keyword(GoKeyword::Switch)))
-        .add(r"type\b", constant!(Keyword(GoKeyword::Type)))
-        .add(r"var\b", constant!(Keyword(GoKeyword::Var)))
+        // Imaginary goes before all other numbers, so that the trailing "i" is not left behind.
+        .add(&imaginary, |c| Literal(GoLiteral::Imaginary(c.get(0).unwrap().as_str())))
         // ...
-        .add(
-            r"[[:digit:]]+\.[[:digit:]]*((e|E)(\+|-)?[[:digit:]]*)?i",
-            |c| Literal(GoLiteral::Imaginary(c.get(0).unwrap().as_str())),
-        ).add(
-            r"[[:digit:]]+(e|E)(\+|-)?[[:digit:]]*i",
-            |c| Literal(GoLiteral::Imaginary(c.get(0).unwrap().as_str()))
-        ).add(
-            r"\.[[:digit:]]+((e|E)(\+|-)?[[:digit:]]*)?i",
-             |c| Literal(GoLiteral::Imaginary(c.get(0).unwrap().as_str()))
-        ).add(
-            r"[[:digit:]]+i",
-            |c| Literal(GoLiteral::Imaginary(c.get(0).unwrap().as_str())))
+        // Floats go before integers, so that "1." or "1e3" is not cut short at "1".
+         .add(&hex_float, |c| Literal(GoLiteral::Float(c.get(0).unwrap().as_str())))
+         .add(&decimal_float, |c| Literal(GoLiteral::Float(c.get(0).unwrap().as_str())))
         // ...
+        // Prefixed bases go first, otherwise their leading "0" would be taken for decimal zero.
+        // Integers must end at a word boundary, so that misplaced separators (e.g. "12_" or
+        // "1__2") make a lexing error, rather than an integer followed by an identifier.
+        // hex_lit     = "0" ( "x" | "X" ) [ "_" ] hex_digits .
          .add(
-            r"[[:digit:]]+\.[[:digit:]]*((e|E)(\+|-)?[[:digit:]]*)?",
-            |c| Literal(GoLiteral::Float(c.get(0).unwrap().as_str()))
-        ).add(
-            r"[[:digit:]]+(e|E)(\+|-)?[[:digit:]]*",
-            |c| Literal(GoLiteral::Float(c.get(0).unwrap().as_str()))
+            r"0(x|X)_?[[:xdigit:]]+(_[[:xdigit:]]+)*\b",
+            |c| Literal(GoLiteral::Integer(c.get(0).unwrap().as_str()))
+        // binary_lit  = "0" ( "b" | "B" ) [ "_" ] binary_digits .
         ).add(
-            r"\.[[:digit:]]+((e|E)(\+|-)?[[:digit:]]*)?",
-            |c| Literal(GoLiteral::Float(c.get(0).unwrap().as_str())))
-        // ...
-         .add(
-            r"[1-9]+[[:digit:]]*",
+            r"0(b|B)_?[01]+(_[01]+)*\b",
             |c| Literal(GoLiteral::Integer(c.get(0).unwrap().as_str()))
+        // octal_lit   = "0" [ "o" | "O" ] [ "_" ] octal_digits .
         ).add(
-            r"0[0-7]*",
+            r"0(o|O)?_?[0-7]+(_[0-7]+)*\b",
             |c| Literal(GoLiteral::Integer(c.get(0).unwrap().as_str()))
+        // decimal_lit = "0" | ( "1" … "9" ) [ [ "_" ] decimal_digits ] .
         ).add(
-            r"0(x|X)[[:xdigit:]]+",
+            r"(?:0|[1-9](_?[[:digit:]])*)\b",
             |c| Literal(GoLiteral::Integer(c.get(0).unwrap().as_str()))
         )
         // ...
+        // Longer operators go first, so that "<<=" is not taken for "<<" or "<".
+         .add(r"<<=", constant!(Operator(GoOperator::ShlAssign)))
+         .add(r">>=", constant!(Operator(GoOperator::ShrAssign)))
+         .add(r"&\^=", constant!(Operator(GoOperator::AndNotAssign)))
+         .add(r"\.\.\.", constant!(Operator(GoOperator::Ellipsis)))
          .add(r"\+=", constant!(Operator(GoOperator::AddAssign)))
          .add(r"-=", constant!(Operator(GoOperator::SubAssign)))
          .add(r"\*=", constant!(Operator(GoOperator::MulAssign)))
@@ -289,9 +394,6 @@ pub fn make_lexer<'a>() -> Lexer<'a, GoToken<'a>> {
          .add(r"&=", constant!(Operator(GoOperator::AndAssign)))
          .add(r"\|=", constant!(Operator(GoOperator::OrAssign)))
          .add(r"\^=", constant!(Operator(GoOperator::XorAssign)))
-         .add(r"<<=", constant!(Operator(GoOperator::ShlAssign)))
-         .add(r">>=", constant!(Operator(GoOperator::ShrAssign)))
-         .add(r"&\^=", constant!(Operator(GoOperator::AndNotAssign)))
          .add(r"&&", constant!(Operator(GoOperator::LAnd)))
          .add(r"\|\|", constant!(Operator(GoOperator::LOr)))
          .add(r"<-", constant!(Operator(GoOperator::Arrow)))
@@ -301,7 +403,10 @@ pub fn make_lexer<'a>() -> Lexer<'a, GoToken<'a>> {
          .add(r"<=", constant!(Operator(GoOperator::LEq)))
          .add(r">=", constant!(Operator(GoOperator::GEq)))
          .add(r":=", constant!(Operator(GoOperator::Define)))
-         .add(r"\.\.\.", constant!(Operator(GoOperator::Ellipsis)))
+         .add(r"==", constant!(Operator(GoOperator::Eql)))
+         .add(r"&\^", constant!(Operator(GoOperator::AndNot)))
+         .add(r"<<", constant!(Operator(GoOperator::Shl)))
+         .add(r">>", constant!(Operator(GoOperator::Shr)))
          .add(r"\(", constant!(Operator(GoOperator::LParen)))
          .add(r"\[", constant!(Operator(GoOperator::LBrack)))
          .add(r"\{", constant!(Operator(GoOperator::LBrace)))
@@ -312,42 +417,242 @@ pub fn make_lexer<'a>() -> Lexer<'a, GoToken<'a>> {
          .add(r"\}", constant!(Operator(GoOperator::RBrace)))
          .add(r";", constant!(Operator(GoOperator::Semicolon)))
          .add(r":", constant!(Operator(GoOperator::Colon)))
-         .add(r"==", constant!(Operator(GoOperator::Eql)))
          .add(r"<", constant!(Operator(GoOperator::Lss)))
          .add(r">", constant!(Operator(GoOperator::Gtr)))
          .add(r"=", constant!(Operator(GoOperator::Assign)))
          .add(r"!", constant!(Operator(GoOperator::Not)))
-         .add(r"&\^", constant!(Operator(GoOperator::AndNot)))
          .add(r"&", constant!(Operator(GoOperator::And)))
          .add(r"\|", constant!(Operator(GoOperator::Or)))
          .add(r"\^", constant!(Operator(GoOperator::Xor)))
-         .add(r"<<", constant!(Operator(GoOperator::Shl)))
-         .add(r">>", constant!(Operator(GoOperator::Shr)))
          .add(r"\+", constant!(Operator(GoOperator::Add)))
          .add(r"-", constant!(Operator(GoOperator::Sub)))
          .add(r"\*", constant!(Operator(GoOperator::Mul)))
          .add(r"/", constant!(Operator(GoOperator::Quo)))
          .add(r"%", constant!(Operator(GoOperator::Rem)))
         // ...
-         .add(rune, |c| {
+         .add(&rune, |c| {
             GoToken::Literal(GoLiteral::Rune(c.get(1).unwrap().as_str()))
         }).add(raw_string, |c| {
             GoToken::Literal(GoLiteral::RawString(c.get(1).unwrap().as_str()))
-        }).add(interpreted_string, |c| {
+        }).add(&interpreted_string, |c| {
             GoToken::Literal(GoLiteral::InterpretedString(c.get(1).unwrap().as_str()))
-        }).add(r"(\p{L}|_)(\p{L}|_|\p{Nd})*", |c| {
+        })
+        // identifier = letter { letter | unicode_digit } .
+        // Note: letter includes "_", so blank identifier `_` is a plain identifier too.
+        .add(r"(\p{L}|_)(\p{L}|_|\p{Nd})*", |c| {
             Ident(c.get(0).unwrap().as_str())
-        }).build()
+        })
+}
+
+/// Same as `GoToken`, but owns its text instead of borrowing it from the source.
+///
+/// Made by `GoToken::into_owned`, e.g. to keep tokens after the source is dropped, or to
+/// deserialize tokens with escapes.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub enum OwnedGoToken {
+    Ident(String),
+    Keyword(GoKeyword),
+    Operator(GoOperator),
+    Literal(OwnedGoLiteral),
+    Comment(String),
+}
+
+/// Same as `GoLiteral`, but owns its text.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub enum OwnedGoLiteral {
+    RawString(String),
+    InterpretedString(String),
+    Integer(String),
+    Float(String),
+    Imaginary(String),
+    Rune(String),
+}
+
+impl<'a> GoToken<'a> {
+    /// Copy borrowed text into an owned token.
+    pub fn into_owned(self) -> OwnedGoToken {
+        match self {
+            Ident(id) => OwnedGoToken::Ident(id.to_owned()),
+            Keyword(kw) => OwnedGoToken::Keyword(kw),
+            Operator(op) => OwnedGoToken::Operator(op),
+            Literal(lit) => OwnedGoToken::Literal(lit.into_owned()),
+            Comment(c) => OwnedGoToken::Comment(c.to_owned()),
+        }
+    }
+
+    /// Whether this is an identifier which is exported from its package, i.e. which starts with
+    /// an uppercase letter, e.g. `Println`, but not `println`.
+    ///
+    /// Letters of any script count, e.g. `Ínt` is exported too. False for other tokens.
+    pub fn is_exported(&self) -> bool {
+        match *self {
+            Ident(id) => id.chars().next().is_some_and(char::is_uppercase),
+            _ => false,
+        }
+    }
+}
+
+impl<'a> GoLiteral<'a> {
+    /// Copy borrowed text into an owned literal.
+    pub fn into_owned(self) -> OwnedGoLiteral {
+        match self {
+            GoLiteral::RawString(s) => OwnedGoLiteral::RawString(s.to_owned()),
+            GoLiteral::InterpretedString(s) => OwnedGoLiteral::InterpretedString(s.to_owned()),
+            GoLiteral::Integer(s) => OwnedGoLiteral::Integer(s.to_owned()),
+            GoLiteral::Float(s) => OwnedGoLiteral::Float(s.to_owned()),
+            GoLiteral::Imaginary(s) => OwnedGoLiteral::Imaginary(s.to_owned()),
+            GoLiteral::Rune(s) => OwnedGoLiteral::Rune(s.to_owned()),
+        }
+    }
+
+    /// Decoded value of a rune literal, e.g. `'\n'` is `'\n'`, and `'\u12e4'` is `'ሤ'`.
+    ///
+    /// Byte values (`\x..` and octal) are taken as code points, as in Go: `'\377'` is `'ÿ'`.
+    ///
+    /// # Returns
+    ///
+    /// `None` for other literals, or if the rune is malformed or a surrogate half.
+    pub fn rune_char(&self) -> Option<char> {
+        let rune = match *self {
+            GoLiteral::Rune(rune) => rune,
+            _ => return None,
+        };
+        let mut chars = rune.chars();
+        let first = chars.next()?;
+        let rest = chars.as_str();
+        if first != '\\' {
+            return if rest.is_empty() { Some(first) } else { None };
+        }
+        let (digits, len, radix) = match rest.chars().next()? {
+            'x' => (&rest[1..], 2, 16),
+            'u' => (&rest[1..], 4, 16),
+            'U' => (&rest[1..], 8, 16),
+            '0'..='7' => (rest, 3, 8),
+            escaped if rest.len() == 1 => {
+                return match escaped {
+                    'a' => Some('\u{7}'),
+                    'b' => Some('\u{8}'),
+                    'f' => Some('\u{c}'),
+                    'n' => Some('\n'),
+                    'r' => Some('\r'),
+                    't' => Some('\t'),
+                    'v' => Some('\u{b}'),
+                    '\\' | '\'' => Some(escaped),
+                    _ => None,
+                };
+            }
+            _ => return None,
+        };
+        if digits.len() != len || !digits.chars().all(|c| c.is_digit(radix)) {
+            return None;
+        }
+        let value = u32::from_str_radix(digits, radix).ok()?;
+        if radix == 8 && value > 0xFF {
+            return None;
+        }
+        // rejects surrogate halves and values beyond U+10FFFF
+        ::std::char::from_u32(value)
+    }
+}
+
+impl OwnedGoToken {
+    /// Borrow text back as a regular token.
+    pub fn as_token(&self) -> GoToken<'_> {
+        match *self {
+            OwnedGoToken::Ident(ref id) => Ident(id),
+            OwnedGoToken::Keyword(kw) => Keyword(kw),
+            OwnedGoToken::Operator(op) => Operator(op),
+            OwnedGoToken::Literal(ref lit) => Literal(lit.as_literal()),
+            OwnedGoToken::Comment(ref c) => Comment(c),
+        }
+    }
+}
+
+impl OwnedGoLiteral {
+    /// Borrow text back as a regular literal.
+    pub fn as_literal(&self) -> GoLiteral<'_> {
+        match *self {
+            OwnedGoLiteral::RawString(ref s) => GoLiteral::RawString(s),
+            OwnedGoLiteral::InterpretedString(ref s) => GoLiteral::InterpretedString(s),
+            OwnedGoLiteral::Integer(ref s) => GoLiteral::Integer(s),
+            OwnedGoLiteral::Float(ref s) => GoLiteral::Float(s),
+            OwnedGoLiteral::Imaginary(ref s) => GoLiteral::Imaginary(s),
+            OwnedGoLiteral::Rune(ref s) => GoLiteral::Rune(s),
+        }
+    }
+}
+
+impl fmt::Display for OwnedGoToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_token().fmt(f)
+    }
+}
+
+impl<'a> Token<'a> for OwnedGoToken {
+    fn describe(&self) -> String {
+        self.to_string()
+    }
+
+    fn describe_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        write!(w, "{}", self)
+    }
+
+    fn descriptor(&self) -> &'static str {
+        self.as_token().descriptor()
+    }
+}
+
+impl<'a> fmt::Display for GoToken<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GoToken::Ident(id) => f.write_str(id),
+            GoToken::Keyword(ref kw) => write!(f, "{:?}", kw),
+            GoToken::Comment(c) => f.write_str(c),
+            _ => write!(f, "{:?}", self),
+        }
+    }
 }
 
 impl<'a> Token<'a> for GoToken<'a> {
     fn describe(&self) -> String {
+        self.to_string()
+    }
+
+    fn describe_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        write!(w, "{}", self)
+    }
+
+    fn identifier(&self) -> Option<&str> {
         match *self {
-            GoToken::Ident(ref id) => id.to_string(),
-            GoToken::Keyword(ref kw) => format!("{:?}", kw),
-            _ => format!("{:?}", self),
+            Ident(id) => Some(id),
+            _ => None,
         }
     }
+
+    fn category(&self) -> Category {
+        match *self {
+            Ident(_) => Category::Identifier,
+            Keyword(_) => Category::Keyword,
+            Operator(op) => match op {
+                GoOperator::LParen
+                | GoOperator::LBrack
+                | GoOperator::LBrace
+                | GoOperator::Comma
+                | GoOperator::Period
+                | GoOperator::RParen
+                | GoOperator::RBrack
+                | GoOperator::RBrace
+                | GoOperator::Semicolon
+                | GoOperator::Colon => Category::Punctuation,
+                _ => Category::Operator,
+            },
+            Literal(_) => Category::Literal,
+            Comment(_) => Category::Comment,
+        }
+    }
+
     /// used for grammar symbol matching at the syntax analysis phaze
     fn descriptor(&self) -> &'static str {
         match *self {
@@ -535,11 +840,16 @@ impl<'a, I> NecessarySemicolon<'a, I> {
         // semicolon spans directly after last token
         let last = after.span.end;
         let loc = Location::new(last.line, last.column + 1, last.absolute + 1);
+        let last = after.position.end;
+        let position = Location::new(last.line, last.column + 1, last.absolute + 1);
 
         TokenMeta {
             span: Span::from_location(loc),
+            position: Span::from_location(position),
             token: GoToken::Operator(GoOperator::Semicolon),
             implicit: true,
+            newline: false,
+            captures: Vec::new(),
         }
     }
 
@@ -636,6 +946,148 @@ where
     }
 }
 
+/// Count statements (or top-level declarations) of Go `source`, i.e. semicolons outside of any
+/// braces `{}` or parenthesis `()`, including implicit ones.
+///
+/// Semicolons of a `for` clause are counted too. Lexing stops at the first error, and only
+/// statements before it are counted.
+pub fn count_statements(source: &str) -> usize {
+    let tokens = necessary_semicolon(drop_comments(make_lexer().into_tokens(source, "".into())));
+    let mut depth = 0usize;
+    let mut count = 0;
+    for meta in tokens {
+        let meta = match meta {
+            Ok(meta) => meta,
+            Err(_) => break,
+        };
+        match meta.token {
+            Operator(GoOperator::LBrace) | Operator(GoOperator::LParen) => depth += 1,
+            Operator(GoOperator::RBrace) | Operator(GoOperator::RParen) => {
+                depth = depth.saturating_sub(1)
+            }
+            Operator(GoOperator::Semicolon) if depth == 0 => count += 1,
+            _ => {}
+        }
+    }
+    count
+}
+
+/// Import declaration of a package, as collected by `imports`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Import {
+    /// Import path as written between its quotes, e.g. `fmt` or `net/http`.
+    pub path: String,
+    /// Package name before the path, e.g. `str` in `import str "strings"`, including `.` of
+    /// dot imports and `_` of blank imports.
+    pub alias: Option<String>,
+}
+
+/// Collect imports of Go `source`, both single ones and grouped in parenthesis.
+///
+/// Lexing stops at the first error, and only imports before it are collected. Malformed import
+/// specs are skipped.
+pub fn imports(source: &str) -> Vec<Import> {
+    let mut tokens = drop_comments(make_lexer().into_tokens(source, "".into()))
+        .map_while(Result::ok)
+        .map(|meta| meta.token)
+        .peekable();
+    let mut imports = Vec::new();
+    while let Some(token) = tokens.next() {
+        if token != Keyword(GoKeyword::Import) {
+            continue;
+        }
+        if tokens.peek() != Some(&Operator(GoOperator::LParen)) {
+            imports.extend(import_spec(&mut tokens));
+            continue;
+        }
+        tokens.next();
+        loop {
+            match tokens.peek() {
+                None | Some(&Operator(GoOperator::RParen)) => break,
+                Some(&Operator(GoOperator::Semicolon)) => {
+                    tokens.next();
+                }
+                Some(_) => imports.extend(import_spec(&mut tokens)),
+            }
+        }
+    }
+    imports
+}
+
+/// Import spec, i.e. an optional package name followed by an import path.
+fn import_spec<'a, I>(tokens: &mut Peekable<I>) -> Option<Import>
+where
+    I: Iterator<Item = GoToken<'a>>,
+{
+    let alias = match tokens.peek() {
+        Some(&Ident(name)) => Some(name.to_owned()),
+        Some(&Operator(GoOperator::Period)) => Some(".".to_owned()),
+        _ => None,
+    };
+    if alias.is_some() {
+        tokens.next();
+    }
+    match tokens.next()? {
+        Literal(GoLiteral::InterpretedString(path)) | Literal(GoLiteral::RawString(path)) => {
+            Some(Import {
+                path: path.to_owned(),
+                alias,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Lex many Go files concurrently, each as `(filename, source)`.
+///
+/// Files are split evenly between as many threads as there are CPUs. Tokens are converted into
+/// `OwnedGoToken`s, and errors do not keep the source, so results outlive the sources and may be
+/// sent across threads.
+///
+/// # Returns
+///
+/// Same results as lexing each file in turn, keyed by filename, in order of `files`.
+pub fn lex_many(
+    files: Vec<(String, String)>,
+) -> Vec<(String, Vec<MetaResult<'static, OwnedGoToken>>)> {
+    let workers = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = files.len().div_ceil(workers).max(1);
+    thread::scope(|scope| {
+        let workers: Vec<_> = files
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    let lexer = make_lexer();
+                    chunk
+                        .iter()
+                        .map(|(filename, source)| {
+                            (filename.clone(), lex_owned(&lexer, filename, source))
+                        }).collect::<Vec<_>>()
+                })
+            }).collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+/// Lex `source` into tokens and errors which do not borrow from it.
+fn lex_owned<'a>(
+    lexer: &Lexer<'a, GoToken<'a>>,
+    filename: &str,
+    source: &'a str,
+) -> Vec<MetaResult<'static, OwnedGoToken>> {
+    let mut results = Vec::new();
+    for next in lexer.tokens(source, filename.to_owned()) {
+        results.push(match next {
+            Ok(meta) => Ok(meta.map(GoToken::into_owned)),
+            Err(e) => Err(Error::new(e.filename, "", e.span, e.description)),
+        });
+    }
+    results
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -660,9 +1112,12 @@ mod test {
 
         let valid_id = [
             r"a",
+            r"foo",
             r"_x9",
             r"ThisVariableIsExported",
             r"αβ",
+            r"π",
+            r"本",
             r"__var__",
             r"e",
             r"i",
@@ -679,13 +1134,334 @@ mod test {
 
         ];
         for id in valid_id.into_iter() {
-            assert_eq!(token(next(&lexer, id)), GoToken::Ident(&id));
+            let tokens = lexer
+                .tokens(id, FILENAME.into())
+                .into_raw()
+                .collect::<Vec<_>>();
+            assert_eq!(tokens, [GoToken::Ident(&id)]);
         }
         for id in illegal_id.into_iter() {
             must_not_match_token!(lexer, id, GoToken::Ident(_));
         }
     }
 
+    #[test]
+    fn test_unicode_id() {
+        let lexer = make_lexer();
+
+        // unicode_letter is \p{L}, and unicode_digit is \p{Nd} of any script
+        let valid_id = [
+            r"λόγος", // Greek
+            r"Ωmega",
+            r"переменная", // Cyrillic
+            r"Счётчик2",
+            r"変数", // CJK
+            r"값",
+            r"x٣", // Arabic-Indic digit
+            r"ग१", // Devanagari letter and digit
+        ];
+        for &id in valid_id.iter() {
+            let tokens = lexer
+                .tokens(id, FILENAME.into())
+                .into_raw()
+                .collect::<Vec<_>>();
+            assert_eq!(tokens, [Ident(id)]);
+        }
+
+        // digits of any script can't start an identifier
+        must_not_match_token!(lexer, r"٣x", GoToken::Ident(_));
+    }
+
+    #[test]
+    fn test_blank_ident() {
+        let lexer = make_lexer();
+
+        // blank identifier and predeclared `iota` are plain identifiers
+        for &id in [r"_", r"iota", r"_x"].iter() {
+            let tokens = lexer
+                .tokens(id, FILENAME.into())
+                .into_raw()
+                .collect::<Vec<_>>();
+            assert_eq!(tokens, [GoToken::Ident(id)]);
+        }
+
+        let tokens = lexer
+            .tokens("const ( _ = iota; KB )", FILENAME.into())
+            .into_raw()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            [
+                Keyword(GoKeyword::Const),
+                Operator(GoOperator::LParen),
+                Ident("_"),
+                Operator(GoOperator::Assign),
+                Ident("iota"),
+                Operator(GoOperator::Semicolon),
+                Ident("KB"),
+                Operator(GoOperator::RParen),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_combined() {
+        let source = r#"
+            package main
+            import "fmt"
+            // say hi
+            func main() {
+                for i := 0x1F; i >= 0; i-- { fmt.Println('\n', 1.5e3i, `raw`, i &^ 2) }
+            }
+        "#;
+        let combined = make_lexer()
+            .into_tokens(source, FILENAME.into())
+            .into_raw()
+            .collect::<Vec<_>>();
+        let per_rule = lexer_builder()
+            .combined(false)
+            .build()
+            .unwrap()
+            .into_tokens(source, FILENAME.into())
+            .into_raw()
+            .collect::<Vec<_>>();
+
+        assert_eq!(combined.len(), 38);
+        assert_eq!(combined, per_rule);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(format!("{}", Ident("main")), "main");
+        assert_eq!(format!("{}", Keyword(GoKeyword::Package)), "Package");
+        assert_eq!(
+            format!("{}", Operator(GoOperator::Semicolon)),
+            Operator(GoOperator::Semicolon).describe()
+        );
+    }
+
+    #[test]
+    fn test_descriptor() {
+        let source = "x if + \"s\" `r` 1 1.5 2i 'c' // c";
+        let descriptors: Vec<_> = make_lexer()
+            .into_tokens(source, FILENAME.into())
+            .into_raw()
+            .map(|t| t.descriptor())
+            .collect();
+        assert_eq!(
+            descriptors,
+            [
+                "identifier",
+                "if",
+                "+",
+                "string_lit",
+                "string_lit",
+                "int_lit",
+                "float_lit",
+                "imaginary_lit",
+                "rune_lit",
+                "comment",
+            ]
+        );
+
+        let histogram = make_lexer().into_tokens("a := b + a", FILENAME.into()).histogram();
+        assert_eq!(histogram["identifier"], 3);
+        assert_eq!(histogram[":="], 1);
+    }
+
+    #[test]
+    fn test_intern() {
+        use lex::Interner;
+
+        let mut interner = Interner::new();
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens("x := y + x", FILENAME.into())
+            .intern(&mut interner)
+            .map(|result| result.unwrap().token)
+            .collect();
+
+        let symbols: Vec<_> = tokens.iter().map(|&(_, symbol)| symbol).collect();
+        assert!(symbols[0].is_some());
+        assert_eq!(symbols[0], symbols[4]);
+        assert_ne!(symbols[0], symbols[2]);
+        assert_eq!(symbols[1], None);
+        assert_eq!(tokens[2].0, Ident("y"));
+
+        assert_eq!(interner.len(), 2);
+        assert_eq!(interner.resolve(symbols[4].unwrap()), "x");
+        assert_eq!(interner.intern("y"), symbols[2].unwrap());
+    }
+
+    #[test]
+    fn test_keyword() {
+        let lexer = make_lexer();
+
+        let keywords = [
+            ("break", GoKeyword::Break),
+            ("case", GoKeyword::Case),
+            ("chan", GoKeyword::Chan),
+            ("const", GoKeyword::Const),
+            ("continue", GoKeyword::Continue),
+            ("default", GoKeyword::Default),
+            ("defer", GoKeyword::Defer),
+            ("else", GoKeyword::Else),
+            ("fallthrough", GoKeyword::Fallthrough),
+            ("for", GoKeyword::For),
+            ("func", GoKeyword::Func),
+            ("go", GoKeyword::Go),
+            ("goto", GoKeyword::Goto),
+            ("if", GoKeyword::If),
+            ("import", GoKeyword::Import),
+            ("interface", GoKeyword::Interface),
+            ("map", GoKeyword::Map),
+            ("package", GoKeyword::Package),
+            ("range", GoKeyword::Range),
+            ("return", GoKeyword::Return),
+            ("select", GoKeyword::Select),
+            ("struct", GoKeyword::Struct),
+            ("switch", GoKeyword::Switch),
+            ("type", GoKeyword::Type),
+            ("var", GoKeyword::Var),
+        ];
+        let not_keywords = [
+            r"funcy", // legal identifier: starts with keyword
+            r"function",
+            r"gopher",
+            r"if_",
+        ];
+        for &(source, keyword) in keywords.iter() {
+            assert_eq!(token(next(&lexer, source)), Keyword(keyword));
+        }
+        for &source in not_keywords.iter() {
+            let tokens = lexer
+                .tokens(source, FILENAME.into())
+                .into_raw()
+                .collect::<Vec<_>>();
+            assert_eq!(tokens, [Ident(source)]);
+        }
+    }
+
+    #[test]
+    fn test_integer() {
+        let lexer = make_lexer();
+
+        let valid_integers = [
+            r"0",
+            r"42",
+            r"4_2",
+            r"1_000",
+            r"170141183460469231731687303715884105727",
+            r"170_141183_460469_231731_687303_715884_105727",
+            r"0600",
+            r"0_600",
+            r"017",
+            r"0o17",
+            r"0O17",
+            r"0b1010",
+            r"0B_1010",
+            r"0xBadFace",
+            r"0xBad_Face",
+            r"0x_67_7a_2f_cc_40_c6",
+        ];
+        let illegal_integers = [
+            r"0x",    // illegal: no hex digits
+            r"0b",    // illegal: no binary digits
+            r"0b12",  // illegal: invalid binary digit
+            r"42_",   // illegal: _ must separate successive digits
+            r"4__2",  // illegal: only one _ at a time
+            r"0_xBad", // illegal: _ must separate successive digits
+        ];
+        for &integer in valid_integers.iter() {
+            let tokens = lexer
+                .tokens(integer, FILENAME.into())
+                .into_raw()
+                .collect::<Vec<_>>();
+            assert_eq!(tokens, [Literal(GoLiteral::Integer(integer))]);
+        }
+        for &integer in illegal_integers.iter() {
+            let tokens = lexer
+                .tokens(integer, FILENAME.into())
+                .into_raw()
+                .collect::<Vec<_>>();
+            assert_ne!(tokens, [Literal(GoLiteral::Integer(integer))]);
+        }
+
+        let misplaced_separators = [
+            r"1__2", // illegal: only one _ at a time
+            r"12_",  // illegal: _ must separate successive digits
+            r"0x_",  // illegal: no hex digits after _
+            r"0x1_", // illegal: _ must separate successive digits
+        ];
+        for &integer in misplaced_separators.iter() {
+            assert!(next(&lexer, integer).unwrap().is_err(), "{}", integer);
+        }
+        // leading underscore makes an identifier, not a number
+        assert_eq!(token(next(&lexer, "_12")), Ident("_12"));
+        assert_eq!(token(next(&lexer, "0x_FF")), Literal(GoLiteral::Integer("0x_FF")));
+    }
+
+    #[test]
+    fn test_operators() {
+        let lexer = make_lexer();
+
+        let operators = [
+            (r"+", GoOperator::Add),
+            (r"-", GoOperator::Sub),
+            (r"*", GoOperator::Mul),
+            (r"/", GoOperator::Quo),
+            (r"%", GoOperator::Rem),
+            (r"&", GoOperator::And),
+            (r"|", GoOperator::Or),
+            (r"^", GoOperator::Xor),
+            (r"<<", GoOperator::Shl),
+            (r">>", GoOperator::Shr),
+            (r"&^", GoOperator::AndNot),
+            (r"+=", GoOperator::AddAssign),
+            (r"-=", GoOperator::SubAssign),
+            (r"*=", GoOperator::MulAssign),
+            (r"/=", GoOperator::QuoAssign),
+            (r"%=", GoOperator::RemAssign),
+            (r"&=", GoOperator::AndAssign),
+            (r"|=", GoOperator::OrAssign),
+            (r"^=", GoOperator::XorAssign),
+            (r"<<=", GoOperator::ShlAssign),
+            (r">>=", GoOperator::ShrAssign),
+            (r"&^=", GoOperator::AndNotAssign),
+            (r"&&", GoOperator::LAnd),
+            (r"||", GoOperator::LOr),
+            (r"<-", GoOperator::Arrow),
+            (r"++", GoOperator::Inc),
+            (r"--", GoOperator::Dec),
+            (r"==", GoOperator::Eql),
+            (r"<", GoOperator::Lss),
+            (r">", GoOperator::Gtr),
+            (r"=", GoOperator::Assign),
+            (r"!", GoOperator::Not),
+            (r"!=", GoOperator::NEq),
+            (r"<=", GoOperator::LEq),
+            (r">=", GoOperator::GEq),
+            (r":=", GoOperator::Define),
+            (r"...", GoOperator::Ellipsis),
+            (r"(", GoOperator::LParen),
+            (r"[", GoOperator::LBrack),
+            (r"{", GoOperator::LBrace),
+            (r",", GoOperator::Comma),
+            (r".", GoOperator::Period),
+            (r")", GoOperator::RParen),
+            (r"]", GoOperator::RBrack),
+            (r"}", GoOperator::RBrace),
+            (r";", GoOperator::Semicolon),
+            (r":", GoOperator::Colon),
+        ];
+        for &(source, operator) in operators.iter() {
+            let tokens = lexer
+                .tokens(source, FILENAME.into())
+                .into_raw()
+                .collect::<Vec<_>>();
+            assert_eq!(tokens, [Operator(operator)], "source: {:?}", source);
+        }
+    }
+
     #[test]
     fn test_imaginary() {
         let lexer = make_lexer();
@@ -700,6 +1476,15 @@ mod test {
             r"1E6i",
             r".25i",
             r".12345E+5i",
+            r"123i",
+            r"0123i", // == 123i for backward-compatibility
+            r"0o123i",
+            r"0xabci",
+            r"0b101i",
+            r"1_000i",
+            r"1.5i",
+            r"2.e+3i",
+            r"0x1p-2i",
         ];
         let illegal_imaginary = [
             r"e6i",  // illegal: can't start with i
@@ -708,11 +1493,12 @@ mod test {
             r"4.5",  // illegal: it is float
             r"i",    //illegal: can't start with i
         ];
-        for imaginary in valid_imaginary.into_iter() {
-            assert_eq!(
-                token(next(&lexer, imaginary)),
-                GoToken::Literal(GoLiteral::Imaginary(&imaginary))
-            );
+        for &imaginary in valid_imaginary.iter() {
+            let tokens = lexer
+                .tokens(imaginary, FILENAME.into())
+                .into_raw()
+                .collect::<Vec<_>>();
+            assert_eq!(tokens, [Literal(GoLiteral::Imaginary(imaginary))]);
         }
         for imaginary in illegal_imaginary.into_iter() {
             must_not_match_token!(lexer, imaginary, GoToken::Literal(GoLiteral::Imaginary(_)));
@@ -733,17 +1519,30 @@ mod test {
             r"1E6",
             r".25",
             r".12345E+5",
+            r"1_5.",
+            r"0.15e+0_2",
+            r"0x1p-2",
+            r"0x2.p10",
+            r"0x1.Fp+0",
+            r"0X.8p-0",
+            r"0X_1FFFP-16",
         ];
         let illegal_floats = [
-            r"e6",  // illegal: can't start with exponent
-            r"..6", // illegal: too many dots
-            r"82",  // illegal: it is integer
+            r"e6",      // illegal: can't start with exponent
+            r"..6",     // illegal: too many dots
+            r"82",      // illegal: it is integer
+            r"0x.p1",   // illegal: mantissa has no digits
+            r"1p-2",    // illegal: p exponent requires hexadecimal mantissa
+            r"0x1.5e-2", // illegal: hexadecimal mantissa requires p exponent
+            r"1_.5",    // illegal: _ must separate successive digits
+            r"0x15e-2", // == 0x15e - 2 (integer subtraction)
         ];
-        for float in valid_floats.into_iter() {
-            assert_eq!(
-                token(next(&lexer, float)),
-                GoToken::Literal(GoLiteral::Float(&float))
-            );
+        for &float in valid_floats.iter() {
+            let tokens = lexer
+                .tokens(float, FILENAME.into())
+                .into_raw()
+                .collect::<Vec<_>>();
+            assert_eq!(tokens, [Literal(GoLiteral::Float(float))]);
         }
         for float in illegal_floats.into_iter() {
             must_not_match_token!(lexer, float, Literal(GoLiteral::Float(_)));
@@ -766,16 +1565,25 @@ mod test {
             r"'\xff'",
             r"'\u12e4'",
             r"'\U00101234'",
+            r"'\U0010FFFF'",
+            r"'\uD7FF'",
+            r"'\uE000'",
             r"'\''", // rune literal containing single quote character
+            r"'λ'",  // multi-byte characters of other scripts
+            r"'ж'",
+            r"'😀'",
         ];
         let illegal_runes = [
             r"'aa'",  // illegal: too many characters
             r"'\xa'", // illegal: too few hexadecimal digits
             r"'\0'",  // illegal: too few octal digits
-                      // Regexp-based lexer cannot detect surrogate half,
-                      // r"'\uDFFF'",     // illegal: surrogate half
-                      // ...nor it is capable of understanding Unicode code points.
-                      // r"'\U00110000'", // illegal: invalid Unicode code point
+            r"'ab'",  // illegal: more than one code point
+            r"'\400'", // illegal: octal value over 255
+            r"'\uDFFF'", // illegal: surrogate half
+            r"'\U0000D800'", // illegal: surrogate half
+            r"'\U00110000'", // illegal: invalid Unicode code point
+            r"'\UFFFFFFFF'", // illegal: invalid Unicode code point
+            r#"'\"'"#,        // illegal: double quote escape outside of string literal
         ];
         for rune in valid_runes.into_iter() {
             assert_eq!(
@@ -788,6 +1596,158 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_category() {
+        let source = "func f(x int) { return x + 0x1F // done\n}";
+        let categories: Vec<_> = make_lexer()
+            .into_tokens(source, FILENAME.into())
+            .map(|meta| meta.unwrap().token.category())
+            .collect();
+
+        use lex::Category::*;
+        assert_eq!(
+            categories,
+            [
+                Keyword,     // func
+                Identifier,  // f
+                Punctuation, // (
+                Identifier,  // x
+                Identifier,  // int
+                Punctuation, // )
+                Punctuation, // {
+                Keyword,     // return
+                Identifier,  // x
+                Operator,    // +
+                Literal,     // 0x1F
+                Comment,     // // done
+                Punctuation, // }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_all_keywords() {
+        // keywords of the spec; no wildcard arm, so that a new variant is not forgotten here
+        fn spelling(keyword: GoKeyword) -> &'static str {
+            match keyword {
+                GoKeyword::Break => "break",
+                GoKeyword::Case => "case",
+                GoKeyword::Chan => "chan",
+                GoKeyword::Const => "const",
+                GoKeyword::Continue => "continue",
+                GoKeyword::Default => "default",
+                GoKeyword::Defer => "defer",
+                GoKeyword::Else => "else",
+                GoKeyword::Fallthrough => "fallthrough",
+                GoKeyword::For => "for",
+                GoKeyword::Func => "func",
+                GoKeyword::Go => "go",
+                GoKeyword::Goto => "goto",
+                GoKeyword::If => "if",
+                GoKeyword::Import => "import",
+                GoKeyword::Interface => "interface",
+                GoKeyword::Map => "map",
+                GoKeyword::Package => "package",
+                GoKeyword::Range => "range",
+                GoKeyword::Return => "return",
+                GoKeyword::Select => "select",
+                GoKeyword::Struct => "struct",
+                GoKeyword::Switch => "switch",
+                GoKeyword::Type => "type",
+                GoKeyword::Var => "var",
+            }
+        }
+
+        use self::GoKeyword::*;
+        let keywords = [
+            Break, Case, Chan, Const, Continue, Default, Defer, Else, Fallthrough, For, Func, Go,
+            Goto, If, Import, Interface, Map, Package, Range, Return, Select, Struct, Switch,
+            Type, Var,
+        ];
+        let unique: ::std::collections::BTreeSet<_> = keywords.iter().collect();
+        assert_eq!(unique.len(), 25);
+
+        let lexer = make_lexer();
+        for &keyword in keywords.iter() {
+            let word = spelling(keyword);
+            let tokens: Vec<_> = lexer.tokens(word, FILENAME.into()).collect();
+            match tokens.as_slice() {
+                [Ok(meta)] if meta.token == GoToken::Keyword(keyword) => {}
+                _ => panic!("keyword {:?} is lexed as {:?}", word, tokens),
+            }
+
+            let token = GoToken::Keyword(keyword);
+            assert_eq!(token.descriptor(), word, "descriptor of {:?}", keyword);
+            assert_eq!(token.describe(), format!("{:?}", keyword), "describe of {:?}", word);
+        }
+    }
+
+    #[test]
+    fn test_imports() {
+        let source = r#"
+            package main
+
+            import "fmt"
+
+            import (
+                str "strings"
+                . "math"
+                _ "net/http/pprof" // for side effects
+                `os`
+            )
+
+            func main() {}
+        "#;
+        let import = |path: &str, alias: Option<&str>| Import {
+            path: path.into(),
+            alias: alias.map(String::from),
+        };
+
+        assert_eq!(
+            imports(source),
+            [
+                import("fmt", None),
+                import("strings", Some("str")),
+                import("math", Some(".")),
+                import("net/http/pprof", Some("_")),
+                import("os", None),
+            ]
+        );
+        assert_eq!(imports("package main; import;"), []);
+    }
+
+    #[test]
+    fn test_is_exported() {
+        assert!(Ident("Foo").is_exported());
+        assert!(Ident("Ínt").is_exported());
+        assert!(Ident("Ωmega").is_exported());
+        assert!(!Ident("foo").is_exported());
+        assert!(!Ident("_Foo").is_exported());
+        assert!(!Ident("変数").is_exported());
+        assert!(!Keyword(GoKeyword::Func).is_exported());
+    }
+
+    #[test]
+    fn test_rune_char() {
+        let rune = |s| GoLiteral::Rune(s).rune_char();
+
+        assert_eq!(rune(r"\n"), Some('\n'));
+        assert_eq!(rune(r"\u12e4"), Some('\u{12e4}'));
+        assert_eq!(rune("本"), Some('本'));
+        assert_eq!(rune(r"\U00101234"), Some('\u{101234}'));
+        assert_eq!(rune(r"\x07"), Some('\u{7}'));
+        assert_eq!(rune(r"\377"), Some('\u{ff}'));
+        assert_eq!(rune(r"\'"), Some('\''));
+        assert_eq!(rune(r"\\"), Some('\\'));
+
+        assert_eq!(rune(r"\uD800"), None);
+        assert_eq!(rune(r"\U0000DFFF"), None);
+        assert_eq!(rune(r"\U00110000"), None);
+        assert_eq!(rune(r"\400"), None);
+        assert_eq!(rune("ab"), None);
+        assert_eq!(GoLiteral::Integer("42").rune_char(), None);
+    }
+
     #[test]
     fn test_string_lit() {
         let lexer = make_lexer();
@@ -804,6 +1764,8 @@ mod test {
             r#""日本語""#,
             r#""\u65e5本\U00008a9e""#,
             r#""\xff\u00FF""#,
+            r#""caf\u00e9""#,
+            r#""\a\b\f\n\r\t\v\\\"\101\x41\U0001F600""#,
         ];
 
         for s in raw_strings.into_iter() {
@@ -819,6 +1781,114 @@ mod test {
                 GoToken::Literal(GoLiteral::InterpretedString(&s[1..s.len() - 1]))
             );
         }
+
+        let illegal_strings = [
+            r#""\uD800""#,     // illegal: surrogate half
+            r#""\U00110000""#, // illegal: invalid Unicode code point
+            r#""\400""#,       // illegal: octal value over 255
+            r#""\q""#,         // illegal: unknown escape
+            r#""\'""#,         // illegal: single quote escape outside of rune literal
+            r#""\u00e""#,      // illegal: too few hex digits
+            r#""\U0000e9""#,   // illegal: too few hex digits
+            r#""\x4""#,        // illegal: too few hex digits
+            r#""\10""#,        // illegal: too few octal digits
+        ];
+        for &s in illegal_strings.iter() {
+            assert!(next(&lexer, s).unwrap().is_err());
+        }
+    }
+
+    const COMMENTS: &str = "\
+        // line comment
+        a /* inline */ / b
+        /* multi
+           line */
+        c // trailing";
+
+    #[test]
+    fn test_comments() {
+        let tokens = make_lexer()
+            .into_tokens(COMMENTS, FILENAME.into())
+            .into_raw()
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tokens,
+            [
+                Comment("// line comment"),
+                Ident("a"),
+                Comment("/* inline */"),
+                Operator(GoOperator::Quo),
+                Ident("b"),
+                Comment("/* multi\n           line */"),
+                Ident("c"),
+                Comment("// trailing"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_describe_comments() {
+        let described: Vec<_> = make_lexer()
+            .into_tokens("// hi\nx /* block\n comment */", FILENAME.into())
+            .into_raw()
+            .map(|token| token.describe())
+            .collect();
+
+        assert_eq!(described, ["// hi", "x", "/* block\n comment */"]);
+
+        // spans cover comments exactly, so the newline after a line comment is left to the
+        // next token
+        let source = "a // c\nb /* d */";
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(source, FILENAME.into())
+            .map(Result::unwrap)
+            .collect();
+        for meta in &tokens {
+            assert_eq!(&source[meta.range()], meta.token.describe());
+        }
+        assert_eq!(tokens[2].token, Ident("b"));
+        assert!(tokens[2].newline);
+    }
+
+    #[test]
+    fn test_drop_comments() {
+        let tokens = drop_comments(make_lexer().into_tokens(COMMENTS, FILENAME.into()))
+            .into_raw()
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tokens,
+            [
+                Ident("a"),
+                Operator(GoOperator::Quo),
+                Ident("b"),
+                Ident("c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unrecognized() {
+        let mut tokens = make_lexer().into_tokens("x := @@@", FILENAME.into());
+
+        assert_eq!(Ident("x"), tokens.next().unwrap().unwrap().token);
+        assert_eq!(
+            Operator(GoOperator::Define),
+            tokens.next().unwrap().unwrap().token
+        );
+
+        let error = tokens.next().unwrap().unwrap_err();
+        assert_eq!(FILENAME, error.filename);
+        assert_eq!(5, error.span.start.absolute);
+        assert_eq!(6, error.span.start.column);
+        assert_eq!(
+            Some("No token could be matched at byte 5: \"@@@\""),
+            error.description.as_deref()
+        );
+
+        // stream is over after an error
+        assert!(tokens.next().is_none());
     }
 
     #[test]
@@ -853,6 +1923,201 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_semicolon_statements() {
+        let source = "a := 1\nb := 2";
+        let tokens = necessary_semicolon(make_lexer().into_tokens(source, FILENAME.into()))
+            .into_raw()
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tokens,
+            [
+                Ident("a"),
+                Operator(GoOperator::Define),
+                Literal(GoLiteral::Integer("1")),
+                Operator(GoOperator::Semicolon),
+                Ident("b"),
+                Operator(GoOperator::Define),
+                Literal(GoLiteral::Integer("2")),
+                Operator(GoOperator::Semicolon),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_semicolon_not_after_operator() {
+        let source = "x := a +\n\tb\nreturn\n}";
+        let tokens = necessary_semicolon(make_lexer().into_tokens(source, FILENAME.into()))
+            .into_raw()
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tokens,
+            [
+                Ident("x"),
+                Operator(GoOperator::Define),
+                Ident("a"),
+                Operator(GoOperator::Add),
+                Ident("b"),
+                Operator(GoOperator::Semicolon),
+                Keyword(GoKeyword::Return),
+                Operator(GoOperator::Semicolon),
+                Operator(GoOperator::RBrace),
+                Operator(GoOperator::Semicolon),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_comments() {
+        let source = "a /* x /* y */ z */ b";
+
+        let tokens: Vec<_> = lexer_builder_with(true)
+            .build()
+            .unwrap()
+            .into_tokens(source, FILENAME.into())
+            .into_raw()
+            .collect();
+        assert_eq!(tokens, [Ident("a"), Comment("/* x /* y */ z */"), Ident("b")]);
+
+        // Go does not allow nesting, so the first "*/" ends the comment by default
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(source, FILENAME.into())
+            .into_raw()
+            .collect();
+        assert_eq!(tokens[1], Comment("/* x /* y */"));
+    }
+
+    #[test]
+    fn test_leading_whitespace() {
+        let tokens = make_lexer()
+            .into_tokens("   -", FILENAME.into())
+            .into_raw()
+            .collect::<Vec<_>>();
+
+        assert_eq!(tokens, [Operator(GoOperator::Sub)]);
+    }
+
+    #[test]
+    fn test_newline() {
+        let source = "a \t b\r\n\tc";
+        let newline = make_lexer()
+            .into_tokens(source, FILENAME.into())
+            .map(|meta| meta.unwrap().newline)
+            .collect::<Vec<_>>();
+
+        assert_eq!(newline, [false, false, true]);
+    }
+
+    #[test]
+    fn test_into_owned() {
+        let source = String::from("x := `raw` // done");
+        let owned: Vec<_> = make_lexer()
+            .tokens(&source, FILENAME.into())
+            .into_raw()
+            .map(GoToken::into_owned)
+            .collect();
+        drop(source);
+
+        assert_eq!(
+            owned,
+            [
+                OwnedGoToken::Ident("x".into()),
+                OwnedGoToken::Operator(GoOperator::Define),
+                OwnedGoToken::Literal(OwnedGoLiteral::RawString("raw".into())),
+                OwnedGoToken::Comment("// done".into()),
+            ]
+        );
+        assert_eq!(owned[0].as_token(), Ident("x"));
+        assert_eq!(owned[0].to_string(), "x");
+        assert_eq!(owned[1].descriptor(), ":=");
+    }
+
+    #[test]
+    fn test_count_statements() {
+        let source = "
+            x := f(1, 2); y := 3 // two on the same line
+            if x > y {
+                x++
+                y--
+            }
+            defer func() {
+                recover()
+            }()
+            var (
+                a = 1
+                b = 2
+            )
+            return x
+        ";
+
+        assert_eq!(count_statements(source), 6);
+        assert_eq!(count_statements(""), 0);
+        assert_eq!(count_statements("a := 1\nb := @"), 1);
+    }
+
+    #[test]
+    fn test_lex_many() {
+        let files = vec![
+            ("a.go".to_owned(), "package a\nvar x = 1".to_owned()),
+            ("b.go".to_owned(), "package b\nfunc f() {}".to_owned()),
+            ("c.go".to_owned(), "package c @".to_owned()),
+        ];
+        let sequential: Vec<_> = files
+            .iter()
+            .map(|(filename, source)| {
+                let tokens = make_lexer()
+                    .into_tokens(source, filename.clone())
+                    .map(|next| {
+                        let owned = next.map(|meta| (meta.span, meta.token.into_owned()));
+                        format!("{:?}", owned.map_err(|e| (e.filename, e.span, e.description)))
+                    }).collect::<Vec<_>>();
+                (filename.clone(), tokens)
+            }).collect();
+
+        let parallel: Vec<_> = lex_many(files)
+            .into_iter()
+            .map(|(filename, results)| {
+                let tokens = results
+                    .into_iter()
+                    .map(|next| {
+                        let owned = next.map(|meta| (meta.span, meta.token));
+                        format!("{:?}", owned.map_err(|e| (e.filename, e.span, e.description)))
+                    }).collect::<Vec<_>>();
+                (filename, tokens)
+            }).collect();
+
+        assert_eq!(parallel, sequential);
+        assert_eq!(parallel[2].1.len(), 3);
+        assert!(parallel[2].1[2].starts_with("Err"));
+    }
+
+    #[test]
+    fn test_remaining() {
+        let source = "x := y + 1\n\t@ z := 2";
+        let mut tokens = make_lexer().into_tokens(source, FILENAME.into());
+        assert_eq!(tokens.remaining(), source);
+
+        assert_eq!(tokens.by_ref().filter(Result::is_ok).count(), 5);
+        assert!(tokens.next().is_none());
+        assert_eq!(tokens.remaining(), "@ z := 2");
+
+        let mut tokens = make_lexer().into_tokens("x  ", FILENAME.into());
+        tokens.next().unwrap().unwrap();
+        assert_eq!(tokens.remaining(), "");
+    }
+
+    #[test]
+    fn test_semicolon_implicit() {
+        let source = "a\nb;";
+        let implicit = necessary_semicolon(make_lexer().into_tokens(source, FILENAME.into()))
+            .map(|meta| meta.unwrap().implicit)
+            .collect::<Vec<_>>();
+
+        assert_eq!(implicit, [false, true, false, false]);
+    }
+
     #[test]
     fn test_semicolon_eof() {
         let source = "package main";
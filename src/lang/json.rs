@@ -0,0 +1,162 @@
+//! # JSON lexer
+//!
+//! JSON (JavaScript Object Notation, [RFC 8259]) language consists of the following lexemes:
+//! - structural characters: braces (`{`, `}`), brackets (`[`, `]`), colon (`:`) and comma (`,`);
+//! - strings in double quotes with escapes, including `\uXXXX` (e.g.: `"a\"b"`, `"é"`);
+//! - numbers without leading zeros, with optional fraction and exponent (e.g.: `-0.5e+10`);
+//! - literal names: `true`, `false` and `null`.
+//!
+//! [RFC 8259]: https://tools.ietf.org/html/rfc8259
+use lex::{ascii_whitespace_filter, Lexer, LexerBuilder, Token};
+use std::fmt;
+
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub enum JsonToken<'a> {
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Colon,
+    Comma,
+    /// String without quotes, escapes included.
+    String(&'a str),
+    /// Number as written in the source.
+    Number(&'a str),
+    True,
+    False,
+    Null,
+}
+
+pub fn make_lexer<'a>() -> Lexer<'a, JsonToken<'a>> {
+    // string = quotation-mark *char quotation-mark
+    let string = r#"(?x)
+        "(
+            (?:
+                [^"\\\x00-\x1F]
+                |
+                \\ (?: ["\\/bfnrt] | u[[:xdigit:]]{4} )
+            )*
+        )"
+    "#;
+    // number = [ minus ] int [ frac ] [ exp ]
+    // trailing word boundary rejects leading zeros, e.g. "01".
+    let number = r#"(?x)
+        -?
+        (?: 0 | [1-9][0-9]* )
+        (?: \.[0-9]+ )?
+        (?: [eE][+-]?[0-9]+ )?
+        \b
+    "#;
+
+    LexerBuilder::new()
+        .skip_whitespaces(ascii_whitespace_filter)
+        .add(r"\{", constant!(JsonToken::LBrace))
+        .add(r"\}", constant!(JsonToken::RBrace))
+        .add(r"\[", constant!(JsonToken::LBracket))
+        .add(r"\]", constant!(JsonToken::RBracket))
+        .add(r":", constant!(JsonToken::Colon))
+        .add(r",", constant!(JsonToken::Comma))
+        .add(r"true\b", constant!(JsonToken::True))
+        .add(r"false\b", constant!(JsonToken::False))
+        .add(r"null\b", constant!(JsonToken::Null))
+        .add(string, |c| JsonToken::String(c.get(1).unwrap().as_str()))
+        .add(number, |c| JsonToken::Number(c.get(0).unwrap().as_str()))
+        .build()
+        .unwrap()
+}
+
+impl<'a> fmt::Display for JsonToken<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JsonToken::String(s) => write!(f, "\"{}\"", s),
+            JsonToken::Number(n) => f.write_str(n),
+            _ => f.write_str(self.descriptor()),
+        }
+    }
+}
+
+impl<'a> Token<'a> for JsonToken<'a> {
+    fn describe(&self) -> String {
+        self.to_string()
+    }
+
+    fn describe_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        write!(w, "{}", self)
+    }
+
+    fn descriptor(&self) -> &'static str {
+        match *self {
+            JsonToken::LBrace => "{",
+            JsonToken::RBrace => "}",
+            JsonToken::LBracket => "[",
+            JsonToken::RBracket => "]",
+            JsonToken::Colon => ":",
+            JsonToken::Comma => ",",
+            JsonToken::String(..) => "String",
+            JsonToken::Number(..) => "Number",
+            JsonToken::True => "true",
+            JsonToken::False => "false",
+            JsonToken::Null => "null",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JsonToken::*;
+    use super::*;
+    use lex::TokensExt;
+
+    const FILENAME: &str = "test.json";
+
+    #[test]
+    fn test_lexer() {
+        let tokens: Vec<_> = make_lexer()
+            .into_tokens(r#"{"a":[1,true,null]}"#, FILENAME.into())
+            .into_raw()
+            .collect();
+
+        assert_eq!(
+            tokens,
+            [
+                LBrace,
+                String("a"),
+                Colon,
+                LBracket,
+                Number("1"),
+                Comma,
+                True,
+                Comma,
+                Null,
+                RBracket,
+                RBrace,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_string() {
+        let lexer = make_lexer();
+        for &s in [r#""""#, r#""a\"b""#, r#""\\\/\b\f\n\r\t""#, r#""é𝄞""#].iter() {
+            let tokens: Vec<_> = lexer.tokens(s, FILENAME.into()).into_raw().collect();
+            assert_eq!(tokens, [String(&s[1..s.len() - 1])]);
+        }
+        for &s in [r#""\x""#, r#""\u12""#, "\"\n\""].iter() {
+            let first = lexer.tokens(s, FILENAME.into()).next().unwrap();
+            assert!(first.is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn test_number() {
+        let lexer = make_lexer();
+        for &s in ["0", "-0", "12", "1.5", "-0.5e+10", "3E2", "1e-3"].iter() {
+            let tokens: Vec<_> = lexer.tokens(s, FILENAME.into()).into_raw().collect();
+            assert_eq!(tokens, [Number(s)]);
+        }
+        for &s in ["01", "-", "1.", ".5", "1e"].iter() {
+            let ok = lexer.tokens(s, FILENAME.into()).all(|r| r.is_ok());
+            assert!(!ok, "{:?}", s);
+        }
+    }
+}
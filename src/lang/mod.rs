@@ -1,4 +1,168 @@
+use std::borrow::Cow;
+use std::prelude::v1::*;
+
+#[cfg(feature = "std")]
+pub mod abnf;
 pub mod bnf;
+#[cfg(feature = "std")]
+pub mod bnf_ext;
+#[cfg(feature = "std")]
 pub mod brainfuck;
+#[cfg(feature = "std")]
 pub mod ebnf;
+#[cfg(feature = "std")]
 pub mod golang;
+#[cfg(feature = "std")]
+pub mod json;
+
+/// Resolve backslash escapes (`\"`, `\'`, `\\`, `\n`, `\t`, `\xHH` and `\u{HHHHHH}`) in a quoted
+/// terminal, as written between its quotes.
+///
+/// Hexadecimal escapes are code points, e.g. `\x41` is `A`, and `\u{1F600}` is `😀`.
+/// Any other character after a backslash is kept as is, together with the backslash, and so are
+/// malformed hexadecimal escapes, and those which are not valid code points, e.g. `\u{D800}`.
+/// Borrows the input if there is nothing to unescape.
+pub fn unescape<'a>(s: &'a str) -> Cow<'a, str> {
+    if !s.contains('\\') {
+        return Cow::Borrowed(s);
+    }
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(c @ '"') | Some(c @ '\'') | Some(c @ '\\') => unescaped.push(c),
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(x @ 'x') | Some(x @ 'u') => {
+                let rest = chars.as_str();
+                match hex_escape(x, rest) {
+                    Some((c, len)) => {
+                        unescaped.push(c);
+                        chars = rest[len..].chars();
+                    }
+                    None => {
+                        unescaped.push('\\');
+                        unescaped.push(x);
+                    }
+                }
+            }
+            Some(c) => {
+                unescaped.push('\\');
+                unescaped.push(c);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    Cow::Owned(unescaped)
+}
+
+/// Code point of a hexadecimal escape `\xHH` or `\u{HHHHHH}`, where `x` is the letter after the
+/// backslash, and `rest` follows it.
+///
+/// # Returns
+///
+/// Code point and the length of its hexadecimal part in `rest`, if the escape is well-formed.
+fn hex_escape(x: char, rest: &str) -> Option<(char, usize)> {
+    let (digits, len) = if x == 'x' {
+        (rest.get(..2)?, 2)
+    } else {
+        let end = rest.find('}').filter(|_| rest.starts_with('{'))?;
+        (&rest[1..end], end + 1)
+    };
+    if digits.is_empty() || digits.len() > 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let c = u32::from_str_radix(digits, 16).ok().and_then(::std::char::from_u32)?;
+    Some((c, len))
+}
+
+/// Regex of a terminal between `quote`s, with backslash escapes, which captures the terminal
+/// as written between its quotes (see [`unescape`]).
+///
+/// Hexadecimal escapes must be well-formed, e.g. `\x4` or `\u{}` do not match.
+///
+/// [`unescape`]: fn.unescape.html
+pub fn terminal_regex(quote: char) -> String {
+    let q = regex::escape(&quote.to_string());
+    format!(
+        r"{q}((?:[^{q}\\\n]|\\(?:x[[:xdigit:]]{{2}}|u\{{[[:xdigit:]]{{1,6}}\}}|[^xu\n]))*){q}",
+        q = q
+    )
+}
+
+/// Scanner of nested block comments, e.g. `/* a /* b */ c */`, for use with
+/// `LexerBuilder::add_scanner`.
+///
+/// # Returns
+///
+/// Length of the comment at the start of `source` in bytes, including its outermost `open` and
+/// `close` delimiters, or `None` if there is no comment or it is not closed.
+pub fn nested_comment_len(source: &str, open: &str, close: &str) -> Option<usize> {
+    if !source.starts_with(open) {
+        return None;
+    }
+    let mut depth = 0;
+    let mut i = 0;
+    while i < source.len() {
+        let rest = &source[i..];
+        if rest.starts_with(open) {
+            depth += 1;
+            i += open.len();
+        } else if rest.starts_with(close) {
+            depth -= 1;
+            i += close.len();
+            if depth == 0 {
+                return Some(i);
+            }
+        } else {
+            i += rest.chars().next().unwrap().len_utf8();
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("abc"), "abc");
+        assert_eq!(unescape(r#"a\"b"#), "a\"b");
+        assert_eq!(unescape(r"it\'s"), "it's");
+        assert_eq!(unescape(r#"\\"#), "\\");
+        assert_eq!(unescape(r#"a\tb\n"#), "a\tb\n");
+        assert_eq!(unescape(r#"\r\"#), r#"\r\"#);
+    }
+
+    #[test]
+    fn test_unescape_hex() {
+        assert_eq!(unescape(r"\x41"), "A");
+        assert_eq!(unescape(r"\x41\x42c"), "ABc");
+        assert_eq!(unescape(r"\u{1F600}"), "\u{1F600}");
+        assert_eq!(unescape(r"a\u{41}b"), "aAb");
+        assert_eq!(unescape(r"\u{e9}\xe9"), "éé");
+
+        // malformed escapes are kept as is
+        assert_eq!(unescape(r"\x4"), r"\x4");
+        assert_eq!(unescape(r"\xg1"), r"\xg1");
+        assert_eq!(unescape(r"\u41"), r"\u41");
+        assert_eq!(unescape(r"\u{}"), r"\u{}");
+        assert_eq!(unescape(r"\u{1234567}"), r"\u{1234567}");
+        assert_eq!(unescape(r"\u{D800}"), r"\u{D800}");
+        assert_eq!(unescape(r"\u{110000}"), r"\u{110000}");
+    }
+
+    #[test]
+    fn test_nested_comment_len() {
+        let source = "/* a /* b */ c */ d */";
+        assert_eq!(nested_comment_len(source, "/*", "*/"), Some(17));
+        assert_eq!(nested_comment_len("(* ä (**) *)", "(*", "*)"), Some(13));
+        assert_eq!(nested_comment_len("/* a /* b */", "/*", "*/"), None);
+        assert_eq!(nested_comment_len("a /* b */", "/*", "*/"), None);
+    }
+}
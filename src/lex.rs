@@ -10,22 +10,52 @@
 //! token".
 //!
 //! [Builder pattern]: https://en.wikipedia.org/wiki/Builder_pattern
-use regex::{Captures, Regex};
-use std::cmp::Ordering;
+use regex::{Captures, Regex, RegexSet};
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BTreeMap;
+#[cfg(feature = "std")]
+use std::collections::VecDeque;
 use std::fmt::{self, Debug, Formatter};
+#[cfg(feature = "std")]
+use std::io::Read;
+use std::iter::Rev;
 use std::marker::PhantomData;
+use std::ops::Range;
+use std::prelude::v1::*;
 use std::rc::Rc;
+use std::vec;
 
+/// Set of compiled rules, built by `LexerBuilder`.
+///
+/// Rules are compiled once by `LexerBuilder::build`, so the same lexer may be reused for any
+/// number of sources: streams made by `tokens` share the rules and keep their own state, and
+/// clones are cheap.
 pub struct Lexer<'a, T> {
-    pairs: Rc<Vec<(Regex, Box<TokenFactory<'a, T>>)>>,
+    pairs: Rc<Vec<(Regex, Rule<'a, T>)>>,
+    /// All rules combined, so that candidates are found in one pass.
+    set: Option<Rc<RegexSet>>,
     skip_whitespaces: fn(&'a str) -> &'a str,
+    longest_match: bool,
+    error_recovery: bool,
+    max_tokens: Option<usize>,
+    keep_captures: bool,
+    skip_shebang: bool,
+    tab_width: usize,
 }
 
 impl<'a, T> Clone for Lexer<'a, T> {
     fn clone(&self) -> Self {
         Lexer {
             pairs: Rc::clone(&self.pairs),
+            set: self.set.clone(),
             skip_whitespaces: self.skip_whitespaces,
+            longest_match: self.longest_match,
+            error_recovery: self.error_recovery,
+            max_tokens: self.max_tokens,
+            keep_captures: self.keep_captures,
+            skip_shebang: self.skip_shebang,
+            tab_width: self.tab_width,
         }
     }
 }
@@ -35,6 +65,8 @@ where
     T: Token<'a>,
 {
     /// Wrap lexer into `Tokens` stream without transfer of ownership.
+    ///
+    /// Leading byte order mark of the `source`, if any, is skipped.
     pub fn tokens<'b>(&self, source: &'a str, filename: String) -> Tokens<'a, T> {
         Tokens::new(self.clone(), source, filename)
     }
@@ -44,19 +76,166 @@ where
         Tokens::new(self, source, filename)
     }
 
+    /// Wrap lexer into `LosslessTokens` stream, which yields skipped whitespaces too.
+    ///
+    /// There is no `LexerBuilder` option for this, e.g. `preserve_trivia(true)`, because an option
+    /// can not change the item type of `Tokens`, while skipped pieces are not tokens of type `T`:
+    /// they come wrapped into `Trivia` instead.
+    pub fn lossless_tokens(&self, source: &'a str, filename: String) -> LosslessTokens<'a, T> {
+        LosslessTokens {
+            inner: self.tokens(source, filename),
+            source,
+            end: 0,
+            location: Default::default(),
+            position: Default::default(),
+            pending: None,
+        }
+    }
+
+    /// Find token which spans byte `offset` of the `source`, e.g. the one under the cursor.
+    ///
+    /// Source is lexed only up to that token. Returns `None` if the offset falls between
+    /// tokens or beyond the end of the source, or if lexing fails before reaching it.
+    pub fn token_at(
+        &self,
+        source: &'a str,
+        filename: String,
+        offset: usize,
+    ) -> Option<TokenMeta<T>> {
+        for meta in self.tokens(source, filename) {
+            let meta = meta.ok()?;
+            let range = meta.span.range();
+            if offset < range.end {
+                return if range.start <= offset { Some(meta) } else { None };
+            }
+        }
+        None
+    }
+
+    /// Check that the whole `source` lexes cleanly, without keeping any tokens.
+    ///
+    /// Stops at the first error, which is returned as `Tokens` would yield it.
+    #[allow(clippy::result_large_err)] // error is the same as of `MetaResult`
+    pub fn validate(&self, source: &'a str, filename: String) -> Result<(), ErrorBytes<'a>> {
+        for meta in self.tokens(source, filename) {
+            meta?;
+        }
+        Ok(())
+    }
+
+    /// Lex the whole `source` at once, and split tokens from errors.
+    ///
+    /// Unless `LexerBuilder::error_recovery` is enabled, there is at most one error, and no
+    /// tokens after it.
+    pub fn lex_to_vec(
+        &self,
+        source: &'a str,
+        filename: String,
+    ) -> (Vec<TokenMeta<T>>, Vec<ErrorBytes<'a>>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        for meta in self.tokens(source, filename) {
+            match meta {
+                Ok(meta) => tokens.push(meta),
+                Err(e) => errors.push(e),
+            }
+        }
+        (tokens, errors)
+    }
+
+    /// Lex the first token of `source`, for consumers which drive the lexer by hand over their
+    /// own buffer.
+    ///
+    /// # Returns
+    ///
+    /// Token with the number of bytes it consumed, including whitespaces before it, so that the
+    /// caller can advance past it; the first error; or `None` if there are no more tokens.
+    #[allow(clippy::result_large_err)] // error is the same as of `MetaResult`
+    pub fn next_with_len(
+        &self,
+        source: &'a str,
+    ) -> Option<Result<(usize, TokenMeta<T>), ErrorBytes<'a>>> {
+        let meta = self.tokens(source, String::new()).next()?;
+        Some(meta.map(|meta| (meta.range().end, meta)))
+    }
+
+    /// Lex `source` again after an edit, reusing `old_tokens` of the source before the edit
+    /// where possible, e.g. to keep tokens of an editor's buffer up to date on each keystroke.
+    ///
+    /// The edit replaced bytes `edit` of the old source with `inserted_len` bytes, which are at
+    /// `edit.start..edit.start + inserted_len` in `source`. Tokens which end before the edit are
+    /// kept as is. Lexing resumes right after them, and stops at the first token past the edit
+    /// which is the same as before it; the rest of `old_tokens` are then shifted into place.
+    ///
+    /// Result is the same as tokens of `lex_to_vec`, as long as no token depends on the text
+    /// past the character right after it. Errors are dropped.
+    pub fn relex(
+        &self,
+        old_tokens: &[TokenMeta<T>],
+        source: &'a str,
+        edit: Range<usize>,
+        inserted_len: usize,
+    ) -> Vec<TokenMeta<T>>
+    where
+        T: Clone,
+    {
+        let kept = old_tokens.iter().take_while(|meta| meta.range().end < edit.start).count();
+        let mut result = old_tokens[..kept].to_vec();
+        let mut tokens = self.tokens(source, String::new());
+        if let Some(last) = result.last() {
+            tokens.location = last.span.end;
+            tokens.position = last.position.end;
+            tokens.count = kept;
+        }
+
+        let mut rest = &old_tokens[kept..];
+        let edit_end = edit.start + inserted_len;
+        for meta in tokens.filter_map(Result::ok) {
+            let start = meta.span.start.absolute;
+            if start >= edit_end {
+                // where the token was before the edit, if it was there
+                let old_start = start - inserted_len + edit.len();
+                while rest.first().is_some_and(|old| old.span.start.absolute < old_start) {
+                    rest = &rest[1..];
+                }
+                if let Some(old) = rest.first() {
+                    let same = old.span.start.absolute == old_start
+                        && old.range().len() == meta.range().len()
+                        && old.token == meta.token
+                        // tab stops are not shifted along with columns
+                        && (self.tab_width == 1
+                            || old.position.start.column == meta.position.start.column);
+                    if same {
+                        let shifted: Vec<_> =
+                            rest[1..].iter().map(|next| shift(next, old, &meta)).collect();
+                        result.push(meta);
+                        result.extend(shifted);
+                        return result;
+                    }
+                }
+            }
+            result.push(meta);
+        }
+        result
+    }
+
     /// ```raw
     /// stripped.is_empty() => None
     /// parse(stripped).is_ok() => Some(Ok(...))
     /// parse(stripped).is_err() => Some(Err(...))
     ///     where stripped = skip_whitespaces(source)
     /// ```
+    ///
+    /// Location `at` points to the last consumed character, i.e. lexing resumes right after it.
+    /// Use default location to start from the beginning of the `source`.
     pub fn next(
         &self,
         source: &'a str,
         at: Location<Bytes>,
     ) -> Option<Result<LexerResult<T>, Error<'a, Bytes>>> {
-        assert!(source.as_bytes().len() >= at.absolute);
-        let src = &source[at.absolute..];
+        let offset = if at.is_none() { 0 } else { at.absolute + 1 };
+        assert!(source.as_bytes().len() >= offset);
+        let src = &source[offset..];
 
         let without_whitespace = (self.skip_whitespaces)(src);
         assert!(src.as_bytes().len() >= without_whitespace.as_bytes().len());
@@ -68,7 +247,7 @@ where
             None
         } else {
             Some(Span {
-                start: at,
+                start: first_byte(at, whitespace),
                 end: at_token,
             })
         };
@@ -76,55 +255,214 @@ where
         if without_whitespace.is_empty() {
             None
         } else {
+            let found = match self.set {
+                // only rules which are known to match need to be run again for captures
+                Some(ref set) => {
+                    let candidates = set.matches(without_whitespace).into_iter();
+                    self.select(candidates, without_whitespace)
+                }
+                None => self.select(0..self.pairs.len(), without_whitespace),
+            };
             Some(
-                self.pairs.iter()
-                    // apply regex AND skip mismatches in one shot
-                    .filter_map(|&(ref regex, ref f)| {
-                        regex
-                            .captures(without_whitespace)
-                            .map(|c| (c, f))
-                    }) // type: Iterator<Item=(Captures<'a>, &Box<TokenFactory<T>>)>
-                    // apply token factory to the captures object
-                    .map(|(c, f)| (c.get(0).unwrap().as_str(), f.token(c)))
-                    // take the first one that matches
-                    .next()
+                found
                     // early return `Err` if empty
-                    .ok_or_else(|| Error {
-                        filename: "".into(),
-                        span: Span { start: at, end: at },
-                        source,
-                        description: Some("No token could be matched".to_owned()),
+                    .ok_or_else(|| {
+                        let location = first_byte(at_token, without_whitespace);
+                        Error {
+                            filename: "".into(),
+                            // span covers exactly one (possibly multi-byte) character
+                            span: Span::new(location, at_token + first_char(without_whitespace)),
+                            source,
+                            description: Some(no_match(location, without_whitespace)),
+                        }
                     })
-                    // type: (&str, T)
-                    .map(|(token, t)| {
+                    // type: (&str, T, Captured)
+                    .map(|(token, t, captures)| {
                         let end = at_token + token;
-                        let first_char_len_utf8 = token.chars().next().unwrap().len_utf8();
-                        // first_char is needed to apply possible pending newline caused by skipping whitespaces.
-                        let first_char = &token[..first_char_len_utf8];
+                        // first_byte is needed to apply possible pending newline caused by skipping whitespaces.
                         let token_span = Span {
-                            start: at + whitespace + first_char,
+                            start: first_byte(at_token, token),
                             end,
                         };
 
+                        // relative to the token, while results are relative to the source
+                        let start = token_span.start.absolute;
+                        let captures = captures
+                            .into_iter()
+                            .map(|c| c.map(|r| r.start + start..r.end + start))
+                            .collect();
+
                         LexerResult {
                             whitespace: whitespace_span,
                             token: token_span,
                             location: end,
                             t,
+                            captures,
                         }
                     }))
         }
     }
 }
 
+impl<'a, T> Lexer<'a, T>
+where
+    T: Token<'a>,
+{
+    /// Choose the winning rule among `candidates` (indices of rules, in order) for `source`,
+    /// and make a token out of it.
+    ///
+    /// # Returns
+    ///
+    /// Lexeme, its token, and byte ranges of its capture groups (if `keep_captures` is set).
+    fn select<I>(&self, candidates: I, source: &'a str) -> Option<(&'a str, T, Captured)>
+    where
+        I: Iterator<Item = usize>,
+    {
+        let mut matches = candidates
+            .map(|i| &self.pairs[i])
+            // apply regex (and scanner) AND skip mismatches in one shot
+            .filter_map(|(regex, rule)| {
+                let c = regex.captures(source)?;
+                let len = match *rule {
+                    Rule::Captures(_) => c.get(0).unwrap().end(),
+                    Rule::Scanner(ref scan, _) => scan(source).filter(|&len| len > 0)?,
+                };
+                Some((len, c, rule))
+            });
+        let (len, c, rule) = if self.longest_match {
+            // take the longest one, or the first one among equals
+            matches.min_by_key(|&(len, _, _)| Reverse(len))
+        } else {
+            // take the first one that matches
+            matches.next()
+        }?;
+        let lexeme = &source[..len];
+        let captures = match *rule {
+            _ if !self.keep_captures => Vec::new(),
+            Rule::Captures(_) => c.iter().map(|m| m.map(|m| m.range())).collect(),
+            // regex of a scanner rule does not cover the whole lexeme
+            Rule::Scanner(..) => vec![Some(0..len)],
+        };
+        let token = match *rule {
+            Rule::Captures(ref f) => f.token(c),
+            Rule::Scanner(_, ref f) => f(lexeme),
+        };
+        Some((lexeme, token, captures))
+    }
+}
+
+/// Slice of the first character of `s`, or an empty slice if there is none.
+fn first_char(s: &str) -> &str {
+    let len = s.chars().next().map(char::len_utf8).unwrap_or_default();
+    &s[..len]
+}
+
+/// Beginning of the unlexed source for error messages: up to the end of line, but no more than
+/// a few characters.
+fn snippet(s: &str) -> &str {
+    const MAX_CHARS: usize = 16;
+
+    let line = s.lines().next().unwrap_or_default();
+    match line.char_indices().nth(MAX_CHARS) {
+        Some((i, _)) => &line[..i],
+        None => line,
+    }
+}
+
+/// Description of a lexer error at `location`, where `s` is the unlexed source.
+fn no_match(location: Location<Bytes>, s: &str) -> String {
+    format!(
+        "No token could be matched at byte {}: {:?}",
+        location.absolute,
+        snippet(s),
+    )
+}
+
+/// Description of the error which ends a stream limited by `LexerBuilder::max_tokens`.
+fn too_many_tokens(max: usize) -> String {
+    format!("Too many tokens, at most {} are allowed", max)
+}
+
+/// Same as `at + s`, but a tab advances the column to the next tab stop, see
+/// `LexerBuilder::tab_width`.
+fn add_chars(mut at: Location<Chars>, s: &str, tab_width: usize) -> Location<Chars> {
+    if tab_width == 1 {
+        return at + s;
+    }
+    for piece in s.split_inclusive('\t') {
+        at += piece;
+        if piece.ends_with('\t') {
+            // tab spans up to the column right before the next tab stop
+            at.column = (at.column - 1) / tab_width * tab_width + tab_width;
+        }
+    }
+    at
+}
+
+/// Location of the first byte of non-empty string `s` which directly follows location `at`.
+///
+/// Unlike `at + first_char(s)`, it does not point to the last byte of a multi-byte character.
+fn first_byte(at: Location<Bytes>, s: &str) -> Location<Bytes> {
+    let first = first_char(s);
+    let mut location = at + first;
+    location.absolute -= first.len() - 1;
+    location.column -= first.len() - 1;
+    location
+}
+
+/// Token `meta` which follows token `from` of the old source, moved the same way as `from`
+/// moved to `to` in the new source, see `Lexer::relex`.
+fn shift<T: Clone>(meta: &TokenMeta<T>, from: &TokenMeta<T>, to: &TokenMeta<T>) -> TokenMeta<T> {
+    fn location<M: Metrics>(at: Location<M>, from: Location<M>, to: Location<M>) -> Location<M> {
+        Location {
+            line: at.line - from.line + to.line,
+            // columns change only on the line of the token
+            column: if at.line == from.line {
+                at.column - from.column + to.column
+            } else {
+                at.column
+            },
+            absolute: at.absolute - from.absolute + to.absolute,
+            ..at
+        }
+    }
+
+    let (from_bytes, to_bytes) = (from.span.start, to.span.start);
+    let (from_chars, to_chars) = (from.position.start, to.position.start);
+    TokenMeta {
+        span: Span::new(
+            location(meta.span.start, from_bytes, to_bytes),
+            location(meta.span.end, from_bytes, to_bytes),
+        ),
+        position: Span::new(
+            location(meta.position.start, from_chars, to_chars),
+            location(meta.position.end, from_chars, to_chars),
+        ),
+        captures: meta
+            .captures
+            .iter()
+            .map(|c| {
+                c.as_ref().map(|r| {
+                    r.start - from_bytes.absolute + to_bytes.absolute
+                        ..r.end - from_bytes.absolute + to_bytes.absolute
+                })
+            }).collect(),
+        ..meta.clone()
+    }
+}
+
 pub struct LexerResult<T> {
-    #[allow(dead_code)]
     pub whitespace: Option<Span<Bytes>>,
     pub token: Span<Bytes>,
     pub location: Location<Bytes>,
     pub t: T,
+    /// See `TokenMeta::captures`.
+    pub captures: Captured,
 }
 
+/// Byte ranges of capture groups of a rule, see `TokenMeta::captures`.
+pub type Captured = Vec<Option<Range<usize>>>;
+
 pub type MetaResult<'a, T> = Result<TokenMeta<T>, ErrorBytes<'a>>;
 
 pub trait MetaIter<'a, T>: Iterator<Item = MetaResult<'a, T>> {}
@@ -148,24 +486,102 @@ pub struct Tokens<'a, T> {
     source: &'a str,
     filename: String,
     error: bool,
+    /// Number of tokens yielded so far, see `LexerBuilder::max_tokens`.
+    count: usize,
     /// Next location that lexer should start parsing from, or if the
     /// `location.is_none()` than lexer will start from the beginning.
     location: Location<Bytes>,
+    /// Same as `location`, but measured in characters.
+    position: Location<Chars>,
 }
 
 impl<'a, T: Token<'a>> Tokens<'a, T> {
     fn new(lexer: Lexer<'a, T>, source: &'a str, filename: String) -> Self {
-        Tokens {
+        let mut tokens = Tokens {
             lexer,
             source,
             filename,
             error: false,
-            location: Location {
-                column: 1,
-                ..Default::default()
-            },
+            count: 0,
+            location: Default::default(),
+            position: Default::default(),
+        };
+        // byte order mark is skipped at the start of the source only
+        let mut rest = source;
+        if rest.starts_with(BOM) {
+            tokens.location += BOM;
+            tokens.position += BOM;
+            rest = &rest[BOM.len()..];
+        }
+        if tokens.lexer.skip_shebang && rest.starts_with("#!") {
+            let line = match rest.find('\n') {
+                Some(end) => &rest[..=end],
+                None => rest,
+            };
+            tokens.location += line;
+            tokens.position += line;
+        }
+        tokens
+    }
+}
+
+/// UTF-8 byte order mark.
+const BOM: &str = "\u{FEFF}";
+
+impl<'a, T> Tokens<'a, T> {
+    /// Not yet lexed tail of the source, e.g. to tell where the stream stopped after an error.
+    ///
+    /// Whitespaces are skipped, so the tail starts at the next token, or at the character
+    /// which could not be matched. It is empty once the whole source is lexed.
+    pub fn remaining(&self) -> &'a str {
+        let offset = if self.location.is_none() {
+            0
+        } else {
+            self.location.absolute + 1
+        };
+        (self.lexer.skip_whitespaces)(&self.source[offset..])
+    }
+
+    /// Check the limit of `LexerBuilder::max_tokens` before yielding the token at `span`.
+    ///
+    /// # Returns
+    ///
+    /// Error, if the limit is already reached; the stream ends then.
+    fn too_many(&mut self, span: Span<Bytes>) -> Option<ErrorBytes<'a>> {
+        match self.lexer.max_tokens {
+            Some(max) if self.count >= max => {
+                self.error = true;
+                let description = too_many_tokens(max);
+                Some(Error::new(self.filename.clone(), self.source, span, Some(description)))
+            }
+            _ => {
+                self.count += 1;
+                None
+            }
         }
     }
+
+    /// Move characters-based `position` up to the end of the token at `span`.
+    ///
+    /// # Returns
+    ///
+    /// Same span as `span`, but measured in characters.
+    fn advance(&mut self, span: Span<Bytes>) -> Span<Chars> {
+        let from = if self.location.is_none() {
+            0
+        } else {
+            self.location.absolute + 1
+        };
+        let range = span.range();
+        let skipped = &self.source[from..range.start];
+        let token = &self.source[range];
+
+        let tab_width = self.lexer.tab_width;
+        let before = add_chars(self.position, skipped, tab_width);
+        let start = add_chars(before, first_char(token), tab_width);
+        self.position = add_chars(before, token, tab_width);
+        Span::new(start, self.position)
+    }
 }
 
 impl<'a, T> Iterator for Tokens<'a, T>
@@ -180,17 +596,36 @@ where
         } else {
             match self.lexer.next(self.source, self.location) {
                 Some(Ok(LexerResult {
-                    token, location, t, ..
+                    whitespace,
+                    token,
+                    location,
+                    t,
+                    captures,
                 })) => {
+                    if let Some(error) = self.too_many(token) {
+                        return Some(Err(error));
+                    }
+                    let position = self.advance(token);
                     self.location = location;
+                    let newline = whitespace
+                        .is_some_and(|span| self.source[span.range()].contains('\n'));
                     Some(Ok(TokenMeta {
                         span: token,
+                        position,
                         token: t,
                         implicit: false,
+                        newline,
+                        captures,
                     }))
                 }
                 Some(Err(error)) => {
-                    self.error = true;
+                    if self.lexer.error_recovery {
+                        // skip offending character, and resume right after it
+                        self.advance(error.span);
+                        self.location = error.span.end;
+                    } else {
+                        self.error = true;
+                    }
                     Some(Err(error.filename(self.filename.clone())))
                 }
                 None => {
@@ -202,106 +637,1492 @@ where
     }
 }
 
-pub trait TokensExt<'a, T>
+/// Token or a piece of source skipped between tokens, as yielded by `LosslessTokens`.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub enum Trivia<'a, T> {
+    Token(T),
+    /// Whitespaces (as per `LexerBuilder::skip_whitespaces`) or a byte order mark, as well as
+    /// characters skipped by error recovery.
+    Trivia(&'a str),
+}
+
+impl<'a, T> Token<'a> for Trivia<'a, T>
 where
-    Self: MetaIter<'a, T> + Sized,
     T: Token<'a>,
 {
-    /// Helper for tests.
-    fn into_raw(self) -> TokensRaw<Self, T>;
+    fn describe(&self) -> String {
+        match *self {
+            Trivia::Token(ref t) => t.describe(),
+            Trivia::Trivia(s) => format!("{:?}", s),
+        }
+    }
+
+    fn describe_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        match *self {
+            Trivia::Token(ref t) => t.describe_into(w),
+            Trivia::Trivia(s) => write!(w, "{:?}", s),
+        }
+    }
+
+    fn category(&self) -> Category {
+        match *self {
+            Trivia::Token(ref t) => t.category(),
+            Trivia::Trivia(_) => Category::Punctuation,
+        }
+    }
+
+    fn descriptor(&self) -> &'static str {
+        match *self {
+            Trivia::Token(ref t) => t.descriptor(),
+            Trivia::Trivia(_) => "Trivia",
+        }
+    }
 }
 
-impl<'a, T, I> TokensExt<'a, T> for I
+/// Lossless token stream, made by `Lexer::lossless_tokens`.
+///
+/// Unlike `Tokens`, whatever is skipped between tokens is yielded as `Trivia::Trivia`, so that
+/// concatenated slices of all the spans reconstruct the source byte-for-byte, e.g. for
+/// formatters. Errors are passed through.
+pub struct LosslessTokens<'a, T> {
+    inner: Tokens<'a, T>,
+    source: &'a str,
+    /// Offset of the first byte which is not yielded yet.
+    end: usize,
+    /// Location of the last yielded byte, see `Tokens::location`.
+    location: Location<Bytes>,
+    /// Same as `location`, but measured in characters.
+    position: Location<Chars>,
+    /// Token which follows the trivia being yielded.
+    pending: Option<TokenMeta<T>>,
+}
+
+impl<'a, T> LosslessTokens<'a, T> {
+    /// Yield source up to byte `to` as trivia, if there is anything left.
+    fn trivia(&mut self, to: usize) -> Option<TokenMeta<Trivia<'a, T>>> {
+        if self.end >= to {
+            return None;
+        }
+        let trivia = &self.source[self.end..to];
+        let span = Span::new(first_byte(self.location, trivia), self.location + trivia);
+        let tab_width = self.inner.lexer.tab_width;
+        let position = Span::new(
+            add_chars(self.position, first_char(trivia), tab_width),
+            add_chars(self.position, trivia, tab_width),
+        );
+        self.advance(span, position);
+        Some(TokenMeta {
+            span,
+            position,
+            token: Trivia::Trivia(trivia),
+            implicit: false,
+            newline: false,
+            captures: Vec::new(),
+        })
+    }
+
+    fn advance(&mut self, span: Span<Bytes>, position: Span<Chars>) {
+        self.end = span.range().end;
+        self.location = span.end;
+        self.position = position.end;
+    }
+
+    fn token(&mut self, meta: TokenMeta<T>) -> TokenMeta<Trivia<'a, T>> {
+        self.advance(meta.span, meta.position);
+        meta.map(Trivia::Token)
+    }
+}
+
+impl<'a, T> Iterator for LosslessTokens<'a, T>
 where
-    I: MetaIter<'a, T>,
     T: Token<'a>,
 {
-    fn into_raw(self) -> TokensRaw<Self, T> {
-        TokensRaw {
-            inner: self,
-            _marker: Default::default(),
+    type Item = MetaResult<'a, Trivia<'a, T>>;
+
+    fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+        if let Some(meta) = self.pending.take() {
+            return Some(Ok(self.token(meta)));
+        }
+        match self.inner.next() {
+            Some(Ok(meta)) => match self.trivia(meta.span.range().start) {
+                Some(trivia) => {
+                    self.pending = Some(meta);
+                    Some(Ok(trivia))
+                }
+                None => Some(Ok(self.token(meta))),
+            },
+            Some(Err(e)) => Some(Err(e)),
+            None => {
+                let len = self.source.len();
+                self.trivia(len).map(Ok)
+            }
         }
     }
 }
 
-pub struct TokensRaw<I, T> {
-    inner: I,
-    _marker: PhantomData<T>,
+/// Lexer which can be built for sources of any lifetime, e.g. for chunks of `ReaderTokens`,
+/// which only live until the next read.
+///
+/// Unlike `Lexer`, which is bound to the lifetime of its source, rules of a family can not keep
+/// lexemes past the source they were matched in.
+#[cfg(feature = "std")]
+pub trait LexerFamily: Sized {
+    type Token<'a>: Token<'a>;
+
+    fn lexer<'a>(&self) -> Lexer<'a, Self::Token<'a>>;
+
+    /// Wrap lexer into `ReaderTokens` stream which reads source incrementally.
+    ///
+    /// Tokens borrow from the buffer which is reused while reading, so the stream yields
+    /// `OwnedToken`s instead. The lexer is built anew for each chunk read.
+    fn tokens_from_reader<R>(self, reader: R, filename: String) -> ReaderTokens<R, Self>
+    where
+        R: Read,
+    {
+        ReaderTokens::new(self, reader, filename)
+    }
 }
 
-impl<'a, T, I> Iterator for TokensRaw<I, T>
+/// Iterator over token stream read incrementally from `io::Read`.
+///
+/// Source is read in chunks into the buffer, which is grown while a token (or whitespaces before
+/// it) reaches the end of the buffer, because more input might extend the match. Lexed bytes are
+/// dropped from the buffer as soon as their tokens are complete.
+///
+/// Spans and positions are the same as if the whole source was lexed with `Tokens`, but errors
+/// do not keep the source, so they can not display it.
+#[cfg(feature = "std")]
+pub struct ReaderTokens<R, F> {
+    family: F,
+    reader: R,
+    filename: String,
+    /// See `LexerBuilder::error_recovery`.
+    error_recovery: bool,
+    /// See `LexerBuilder::max_tokens`.
+    max_tokens: Option<usize>,
+    /// See `LexerBuilder::tab_width`.
+    tab_width: usize,
+    error: bool,
+    /// Number of tokens yielded so far, see `LexerBuilder::max_tokens`.
+    count: usize,
+    /// Not yet lexed part of the source.
+    buffer: Vec<u8>,
+    /// Lexed, but not yet yielded tokens and errors.
+    queue: VecDeque<MetaResult<'static, OwnedToken>>,
+    /// Number of bytes to read at once.
+    chunk_size: usize,
+    /// Whether the reader has reached its end.
+    eof: bool,
+    /// Whether the whole source is lexed.
+    done: bool,
+    /// Location of the last consumed byte, see `Tokens::location`.
+    location: Location<Bytes>,
+    /// Same as `location`, but measured in characters.
+    position: Location<Chars>,
+}
+
+/// End of input is described as `EOF`, see `TokensExt::with_eof`.
+impl<'a, T> Token<'a> for Option<T>
 where
-    I: MetaIter<'a, T>,
+    T: Token<'a>,
 {
-    type Item = T;
+    fn describe(&self) -> String {
+        match *self {
+            Some(ref t) => t.describe(),
+            None => "EOF".into(),
+        }
+    }
 
-    fn next(&mut self) -> Option<T> {
-        match self.inner.next() {
-            Some(Ok(TokenMeta { token, .. })) => Some(token),
-            _ => None,
+    fn describe_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        match *self {
+            Some(ref t) => t.describe_into(w),
+            None => w.write_str("EOF"),
+        }
+    }
+
+    fn identifier(&self) -> Option<&str> {
+        self.as_ref().and_then(Token::identifier)
+    }
+
+    fn category(&self) -> Category {
+        self.as_ref().map(Token::category).unwrap_or(Category::Punctuation)
+    }
+
+    fn descriptor(&self) -> &'static str {
+        match *self {
+            Some(ref t) => t.descriptor(),
+            None => "EOF",
         }
     }
 }
 
-pub struct LexerBuilder<'a, T> {
-    pairs: Vec<(Regex, Box<TokenFactory<'a, T>>)>,
-    skip_whitespaces: fn(&'a str) -> &'a str,
+/// Token detached from the source, as yielded by `ReaderTokens`.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct OwnedToken {
+    /// Same as `Token::descriptor` of the original token.
+    pub descriptor: &'static str,
+    /// Same as `Token::describe` of the original token.
+    pub description: String,
 }
 
-impl<'a, T> LexerBuilder<'a, T>
+impl OwnedToken {
+    pub fn new<'a, T: Token<'a>>(token: &T) -> Self {
+        OwnedToken {
+            descriptor: token.descriptor(),
+            description: token.describe(),
+        }
+    }
+}
+
+impl<'a> Token<'a> for OwnedToken {
+    fn describe(&self) -> String {
+        self.description.clone()
+    }
+
+    fn describe_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        w.write_str(&self.description)
+    }
+
+    fn descriptor(&self) -> &'static str {
+        self.descriptor
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R, F> ReaderTokens<R, F>
 where
-    T: Token<'a>,
+    R: Read,
+    F: LexerFamily,
 {
-    pub fn new() -> Self {
-        LexerBuilder {
-            pairs: Vec::new(),
-            skip_whitespaces: |x| x,
+    fn new(family: F, reader: R, filename: String) -> Self {
+        let (error_recovery, max_tokens, tab_width) = {
+            let lexer = family.lexer();
+            (lexer.error_recovery, lexer.max_tokens, lexer.tab_width)
+        };
+        ReaderTokens {
+            family,
+            reader,
+            filename,
+            error_recovery,
+            max_tokens,
+            tab_width,
+            error: false,
+            count: 0,
+            buffer: Vec::new(),
+            queue: VecDeque::new(),
+            chunk_size: 8 * 1024,
+            eof: false,
+            done: false,
+            location: Default::default(),
+            position: Default::default(),
+        }
+    }
+
+    /// Set up number of bytes to read at once.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0);
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Read one more chunk into the buffer.
+    fn fill(&mut self) -> Result<(), String> {
+        let len = self.buffer.len();
+        self.buffer.resize(len + self.chunk_size, 0);
+        let read = loop {
+            match self.reader.read(&mut self.buffer[len..]) {
+                Ok(read) => break read,
+                Err(ref e) if e.kind() == ::std::io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.buffer.truncate(len);
+                    return Err(format!("Could not read source: {}", e));
+                }
+            }
+        };
+        self.buffer.truncate(len + read);
+        self.eof = read == 0;
+        Ok(())
+    }
+
+    fn error(&self, location: Location<Bytes>, description: String) -> ErrorBytes<'static> {
+        Error::new(
+            self.filename.clone(),
+            "",
+            Span::from_location(location),
+            Some(description),
+        )
+    }
+
+    /// Lex tokens of the buffer which are known to be complete into the queue, and drop their
+    /// bytes from the buffer.
+    fn lex(&mut self) {
+        let (valid, invalid) = match ::std::str::from_utf8(&self.buffer) {
+            Ok(text) => (text.len(), false),
+            // incomplete character might be completed by the next chunk
+            Err(e) => (e.valid_up_to(), e.error_len().is_some() || self.eof),
+        };
+        let complete = self.eof || invalid;
+        let mut results = Vec::new();
+        // number of lexed bytes of the buffer
+        let mut lexed = 0;
+        {
+            // lexer, and so any lexeme it makes, lives no longer than this borrow of the buffer
+            let text = ::std::str::from_utf8(&self.buffer[..valid]).unwrap();
+            let lexer = self.family.lexer();
+            loop {
+                let rest = &text[lexed..];
+                let next = lexer.next(rest, Default::default()).map(|result| {
+                    result
+                        .map(|r| (r.token.range(), OwnedToken::new(&r.t), r.captures))
+                        .map_err(|e| e.span.start.absolute)
+                });
+                match next {
+                    None if invalid => {
+                        // any single byte would do to point right past the valid text
+                        let location = self.location + rest + " ";
+                        results.push(Err(self.error(location, "Source is not valid UTF-8".into())));
+                        self.error = true;
+                        break;
+                    }
+                    None => {
+                        self.done = complete;
+                        break;
+                    }
+                    Some(Ok((range, owned, captures))) => {
+                        if range.end == rest.len() && !complete {
+                            break;
+                        }
+                        let whitespace = &rest[..range.start];
+                        let lexeme = &rest[range.clone()];
+
+                        let at_token = self.location + whitespace;
+                        let span = Span::new(first_byte(at_token, lexeme), at_token + lexeme);
+                        let before = add_chars(self.position, whitespace, self.tab_width);
+                        let start = add_chars(before, first_char(lexeme), self.tab_width);
+                        let position = Span::new(start, add_chars(before, lexeme, self.tab_width));
+                        self.location = span.end;
+                        self.position = position.end;
+
+                        // relative to the rest, while results are relative to the whole source
+                        let (from, to) = (range.start, span.start.absolute);
+                        let captures = captures
+                            .into_iter()
+                            .map(|c| c.map(|r| r.start - from + to..r.end - from + to))
+                            .collect();
+
+                        results.push(Ok(TokenMeta {
+                            span,
+                            position,
+                            token: owned,
+                            implicit: false,
+                            newline: whitespace.contains('\n'),
+                            captures,
+                        }));
+                        lexed += range.end;
+                    }
+                    Some(Err(_)) if !complete => break,
+                    Some(Err(at)) => {
+                        let bad = &rest[at..];
+                        let location = first_byte(self.location + &rest[..at], bad);
+                        let description = no_match(location, bad);
+                        if !self.error_recovery {
+                            results.push(Err(self.error(location, description)));
+                            self.error = true;
+                            break;
+                        }
+                        // skip offending character, and resume right after it
+                        let skipped = &rest[..at + first_char(bad).len()];
+                        let span = Span::new(location, self.location + skipped);
+                        self.location = span.end;
+                        self.position = add_chars(self.position, skipped, self.tab_width);
+                        let error = Error::new(self.filename.clone(), "", span, Some(description));
+                        results.push(Err(error));
+                        lexed += skipped.len();
+                    }
+                }
+            }
         }
+        self.buffer.drain(..lexed);
+        self.queue.extend(results);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R, F> Iterator for ReaderTokens<R, F>
+where
+    R: Read,
+    F: LexerFamily,
+{
+    type Item = MetaResult<'static, OwnedToken>;
+
+    fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+        loop {
+            match self.queue.pop_front() {
+                Some(Ok(ref meta)) if Some(self.count) == self.max_tokens => {
+                    let description = too_many_tokens(self.count);
+                    let error = Error::new(self.filename.clone(), "", meta.span, Some(description));
+                    self.error = true;
+                    self.queue.clear();
+                    return Some(Err(error));
+                }
+                Some(next) => {
+                    if next.is_ok() {
+                        self.count += 1;
+                    }
+                    return Some(next);
+                }
+                None if self.error || self.done => return None,
+                None => {}
+            }
+            if let Err(description) = self.fill() {
+                let location = self.location;
+                self.error = true;
+                return Some(Err(self.error(location, description)));
+            }
+            self.lex();
+        }
+    }
+}
+
+pub trait TokensExt<'a, T>
+where
+    Self: MetaIter<'a, T> + Sized,
+    T: Token<'a>,
+{
+    /// Helper for tests.
+    fn into_raw(self) -> TokensRaw<Self, T>;
+
+    /// Collect tokens without their metadata, e.g. to compare them with expected tokens in
+    /// tests regardless of positions.
+    ///
+    /// Same as `into_raw().collect()`: tokens after an error are dropped.
+    fn tokens_only(self) -> Vec<T>;
+
+    /// Wrap stream into adapter with one token lookahead.
+    ///
+    /// Unlike `Iterator::peekable`, resulting adapter is bound to `MetaIter`
+    /// and `peek`s at the `MetaResult` directly.
+    fn into_peekable(self) -> TokensPeekable<'a, Self, T>;
+
+    /// Render tokens back via `Token::describe`, separated by single spaces.
+    ///
+    /// Errors are skipped.
+    fn describe_all(self) -> String;
+
+    /// Render tokens back via `Token::describe`, like `describe_all`, but with minimal
+    /// separators: descriptions which end with a newline (e.g. of line comments) are followed by
+    /// that newline instead of a space, and only if there are more tokens after them.
+    ///
+    /// Errors are skipped.
+    fn render(self) -> String;
+
+    /// Render tokens for debugging, one per line as `line:column descriptor description`,
+    /// e.g. `2:9 NonTerminal <A>`.
+    ///
+    /// Errors are rendered as `!:! description` in place.
+    fn dump(self) -> String;
+
+    /// Count tokens by their `Token::descriptor`, e.g. how many non-terminals there are.
+    ///
+    /// Errors are counted under the `"<error>"` key.
+    fn histogram(self) -> BTreeMap<&'static str, usize>;
+
+    /// Collect all tokens, or stop at the first error and return it.
+    ///
+    /// Shortcut for `collect::<Result<Vec<_>, _>>()`, which spares type annotations.
+    // error is the same as of `MetaResult`
+    #[allow(clippy::result_large_err)]
+    fn try_collect(self) -> Result<Vec<TokenMeta<T>>, ErrorBytes<'a>>;
+
+    /// Keep only tokens whose `Token::descriptor` equals `descriptor`, e.g. only non-terminals.
+    ///
+    /// Errors are kept too.
+    fn only(self, descriptor: &'static str) -> Only<'a, Self, T>;
+
+    /// Split stream into groups of tokens separated by tokens which match `predicate`,
+    /// e.g. into rules separated by delimiters.
+    ///
+    /// Separators are not included in groups. Separator after the last group is optional.
+    /// Error is yielded in place of the group it occurred in, and ends the stream.
+    fn split_on<P>(self, predicate: P) -> SplitOn<'a, Self, T, P>
+    where
+        P: FnMut(&T) -> bool;
+
+    /// Convert tokens with `f`, e.g. into a simplified kind for syntax highlighting.
+    ///
+    /// Metadata (span, position and flags) is kept as is, and errors are passed through.
+    fn map_token<U, F>(self, f: F) -> MapToken<'a, Self, T, F>
+    where
+        F: FnMut(T) -> U;
+
+    /// Pair each token with a symbol of its identifier (see `Token::identifier`) in `interner`,
+    /// so that identifiers can be compared by integers rather than by strings.
+    ///
+    /// Tokens without identifiers are paired with `None`. Errors are passed through.
+    fn intern<'i>(self, interner: &'i mut Interner) -> Intern<'a, 'i, Self, T>;
+
+    /// Wrap tokens into `Some`, and append `None` as an end of input marker after the last of
+    /// them, e.g. so that a parser need not check for the end of stream separately.
+    ///
+    /// End of input is an implicit token which spans right after the end of the `source`, i.e.
+    /// at `source.len()`. It is not appended if the stream ends with an error. Tokens of a lexer
+    /// with `LexerBuilder::tab_width` need `WithEof::tab_width` too, for the column of the end.
+    fn with_eof(self, source: &'a str) -> WithEof<'a, Self, T>;
+
+    /// Fuse adjacent string literals into one token with their concatenated values, e.g. for
+    /// languages where `"ab" "cd"` is the same as `"abcd"`.
+    ///
+    /// String literals are the tokens for which `value` returns their values. A single string is
+    /// kept as is, while two or more of them in a row become `Fused::Strings`, which spans all of
+    /// them. Errors are passed through.
+    fn fuse_strings<F>(self, value: F) -> FuseStrings<'a, Self, T, F>
+    where
+        F: FnMut(&T) -> Option<Cow<'a, str>>;
+
+    /// Collect the whole stream, and yield it backwards, from the last token to the first one,
+    /// e.g. for parsers which scan backwards.
+    ///
+    /// Spans are kept as is, so they are descending. An error, if any, comes first, as it ends
+    /// the stream.
+    fn reversed(self) -> Rev<vec::IntoIter<MetaResult<'a, T>>>;
+
+    /// Merge runs of errors which directly follow each other, e.g. one per offending character
+    /// with `LexerBuilder::error_recovery`, into one error which spans the whole run.
+    ///
+    /// Merged error keeps the description of the first one. Tokens are passed through.
+    fn coalesce_errors(self) -> CoalesceErrors<'a, Self, T>;
+}
+
+impl<'a, T, I> TokensExt<'a, T> for I
+where
+    I: MetaIter<'a, T>,
+    T: Token<'a>,
+{
+    fn into_raw(self) -> TokensRaw<Self, T> {
+        TokensRaw {
+            inner: self,
+            _marker: Default::default(),
+        }
+    }
+
+    fn tokens_only(self) -> Vec<T> {
+        self.into_raw().collect()
+    }
+
+    fn into_peekable(self) -> TokensPeekable<'a, Self, T> {
+        TokensPeekable {
+            inner: self,
+            peeked: None,
+        }
+    }
+
+    fn describe_all(self) -> String {
+        let mut described = String::new();
+        for meta in self.filter_map(Result::ok) {
+            if !described.is_empty() {
+                described.push(' ');
+            }
+            // writing into a string never fails
+            meta.token.describe_into(&mut described).unwrap();
+        }
+        described
+    }
+
+    fn render(self) -> String {
+        let mut rendered = String::new();
+        let mut newline = false;
+        for meta in self.filter_map(Result::ok) {
+            if newline {
+                rendered.push('\n');
+            } else if !rendered.is_empty() {
+                rendered.push(' ');
+            }
+            // writing into a string never fails
+            meta.token.describe_into(&mut rendered).unwrap();
+            newline = rendered.ends_with('\n');
+            if newline {
+                rendered.pop();
+            }
+        }
+        rendered
+    }
+
+    fn dump(self) -> String {
+        let mut dump = String::new();
+        for next in self {
+            match next {
+                Ok(meta) => {
+                    let (line, column) = (meta.line(), meta.column());
+                    let descriptor = meta.token.descriptor();
+                    let description = meta.token.describe();
+                    dump += &format!("{}:{} {} {}\n", line, column, descriptor, description);
+                }
+                Err(e) => {
+                    let description = e.description.unwrap_or_else(|| "<unknown error>".into());
+                    dump += &format!("!:! {}\n", description);
+                }
+            }
+        }
+        dump
+    }
+
+    fn histogram(self) -> BTreeMap<&'static str, usize> {
+        let mut histogram = BTreeMap::new();
+        for next in self {
+            let descriptor = match next {
+                Ok(meta) => meta.token.descriptor(),
+                Err(_) => "<error>",
+            };
+            *histogram.entry(descriptor).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    fn try_collect(self) -> Result<Vec<TokenMeta<T>>, ErrorBytes<'a>> {
+        let mut tokens = Vec::new();
+        for next in self {
+            tokens.push(next?);
+        }
+        Ok(tokens)
+    }
+
+    fn only(self, descriptor: &'static str) -> Only<'a, Self, T> {
+        Only {
+            inner: self,
+            descriptor,
+            _marker: Default::default(),
+        }
+    }
+
+    fn split_on<P>(self, predicate: P) -> SplitOn<'a, Self, T, P>
+    where
+        P: FnMut(&T) -> bool,
+    {
+        SplitOn {
+            inner: self,
+            predicate,
+            done: false,
+            _marker: Default::default(),
+        }
+    }
+
+    fn intern<'i>(self, interner: &'i mut Interner) -> Intern<'a, 'i, Self, T> {
+        Intern {
+            inner: self,
+            interner,
+            _marker: Default::default(),
+        }
+    }
+
+    fn with_eof(self, source: &'a str) -> WithEof<'a, Self, T> {
+        WithEof {
+            inner: self,
+            source,
+            tab_width: 1,
+            error: false,
+            done: false,
+            _marker: Default::default(),
+        }
+    }
+
+    fn map_token<U, F>(self, f: F) -> MapToken<'a, Self, T, F>
+    where
+        F: FnMut(T) -> U,
+    {
+        MapToken {
+            inner: self,
+            f,
+            _marker: Default::default(),
+        }
+    }
+
+    fn fuse_strings<F>(self, value: F) -> FuseStrings<'a, Self, T, F>
+    where
+        F: FnMut(&T) -> Option<Cow<'a, str>>,
+    {
+        FuseStrings {
+            inner: self,
+            value,
+            pending: None,
+        }
+    }
+
+    fn reversed(self) -> Rev<vec::IntoIter<MetaResult<'a, T>>> {
+        self.collect::<Vec<_>>().into_iter().rev()
+    }
+
+    fn coalesce_errors(self) -> CoalesceErrors<'a, Self, T> {
+        CoalesceErrors {
+            inner: self,
+            pending: None,
+        }
+    }
+}
+
+pub struct Only<'a, I, T> {
+    inner: I,
+    descriptor: &'static str,
+    _marker: PhantomData<MetaResult<'a, T>>,
+}
+
+impl<'a, I, T> Iterator for Only<'a, I, T>
+where
+    I: MetaIter<'a, T>,
+    T: Token<'a>,
+{
+    type Item = MetaResult<'a, T>;
+
+    fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+        let descriptor = self.descriptor;
+        self.inner.find(|next| match *next {
+            Ok(ref meta) => meta.token.descriptor() == descriptor,
+            Err(_) => true,
+        })
+    }
+}
+
+pub struct SplitOn<'a, I, T, P> {
+    inner: I,
+    predicate: P,
+    done: bool,
+    _marker: PhantomData<MetaResult<'a, T>>,
+}
+
+impl<'a, I, T, P> Iterator for SplitOn<'a, I, T, P>
+where
+    I: MetaIter<'a, T>,
+    T: Token<'a>,
+    P: FnMut(&T) -> bool,
+{
+    type Item = Result<Vec<TokenMeta<T>>, ErrorBytes<'a>>;
+
+    fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+        if self.done {
+            return None;
+        }
+        let mut group = Vec::new();
+        loop {
+            match self.inner.next() {
+                Some(Ok(meta)) => {
+                    if (self.predicate)(&meta.token) {
+                        return Some(Ok(group));
+                    }
+                    group.push(meta);
+                }
+                Some(Err(e)) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+                None => {
+                    self.done = true;
+                    // no group after the last separator
+                    return if group.is_empty() { None } else { Some(Ok(group)) };
+                }
+            }
+        }
+    }
+}
+
+pub struct MapToken<'a, I, T, F> {
+    inner: I,
+    f: F,
+    _marker: PhantomData<MetaResult<'a, T>>,
+}
+
+impl<'a, I, T, U, F> Iterator for MapToken<'a, I, T, F>
+where
+    I: MetaIter<'a, T>,
+    T: Token<'a>,
+    F: FnMut(T) -> U,
+{
+    type Item = MetaResult<'a, U>;
+
+    fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+        match self.inner.next()? {
+            Ok(meta) => Some(Ok(meta.map(&mut self.f))),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Interned identifier, see `Interner`.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct Symbol(pub u32);
+
+/// Table of identifiers, each of which is stored once and referred to by its `Symbol`.
+#[derive(Clone, Debug, Default)]
+pub struct Interner {
+    symbols: BTreeMap<String, Symbol>,
+    names: Vec<String>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Symbol of `name`, which is the same for every occurrence of the same `name`.
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(self.names.len() as u32);
+        self.symbols.insert(name.to_owned(), symbol);
+        self.names.push(name.to_owned());
+        symbol
+    }
+
+    /// Name which `symbol` was made for.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `symbol` was made by another interner.
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0 as usize]
+    }
+
+    /// Number of distinct names.
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+pub struct Intern<'a, 'i, I, T> {
+    inner: I,
+    interner: &'i mut Interner,
+    _marker: PhantomData<MetaResult<'a, T>>,
+}
+
+impl<'a, 'i, I, T> Iterator for Intern<'a, 'i, I, T>
+where
+    I: MetaIter<'a, T>,
+    T: Token<'a>,
+{
+    type Item = MetaResult<'a, (T, Option<Symbol>)>;
+
+    fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+        match self.inner.next()? {
+            Ok(meta) => {
+                let symbol = meta.token.identifier().map(|name| self.interner.intern(name));
+                Some(Ok(meta.map(|token| (token, symbol))))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+pub struct WithEof<'a, I, T> {
+    inner: I,
+    source: &'a str,
+    /// See `LexerBuilder::tab_width`.
+    tab_width: usize,
+    /// Whether the last item was an error.
+    error: bool,
+    /// Whether the end of input is yielded already.
+    done: bool,
+    _marker: PhantomData<MetaResult<'a, T>>,
+}
+
+impl<'a, I, T> WithEof<'a, I, T> {
+    /// Same as `LexerBuilder::tab_width` of the lexer which made tokens, so that the column of
+    /// the end of input agrees with theirs.
+    ///
+    /// # Panics
+    ///
+    /// If `n` is zero.
+    pub fn tab_width(mut self, n: usize) -> Self {
+        assert!(n > 0, "tab width must be positive");
+        self.tab_width = n;
+        self
+    }
+}
+
+impl<'a, I, T> Iterator for WithEof<'a, I, T>
+where
+    I: MetaIter<'a, T>,
+    T: Token<'a>,
+{
+    type Item = MetaResult<'a, Option<T>>;
+
+    fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+        if self.done {
+            return None;
+        }
+        match self.inner.next() {
+            Some(Ok(meta)) => {
+                self.error = false;
+                Some(Ok(meta.map(Some)))
+            }
+            Some(Err(e)) => {
+                self.error = true;
+                Some(Err(e))
+            }
+            None => {
+                self.done = true;
+                if self.error {
+                    return None;
+                }
+                // phantom character right after the end of the source
+                let location = Location::<Bytes>::default() + self.source + " ";
+                let position = add_chars(Default::default(), self.source, self.tab_width) + " ";
+                Some(Ok(TokenMeta {
+                    span: Span::from_location(location),
+                    position: Span::from_location(position),
+                    token: None,
+                    implicit: true,
+                    newline: false,
+                    captures: Vec::new(),
+                }))
+            }
+        }
+    }
+}
+
+/// Token or adjacent string literals fused into one, as yielded by `TokensExt::fuse_strings`.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub enum Fused<T> {
+    Token(T),
+    /// Two or more string literals: descriptor of the first of them, and their concatenated
+    /// values.
+    Strings(&'static str, String),
+}
+
+impl<'a, T> Token<'a> for Fused<T>
+where
+    T: Token<'a>,
+{
+    fn describe(&self) -> String {
+        match *self {
+            Fused::Token(ref t) => t.describe(),
+            Fused::Strings(_, ref value) => format!("{:?}", value),
+        }
+    }
+
+    fn describe_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        match *self {
+            Fused::Token(ref t) => t.describe_into(w),
+            Fused::Strings(_, ref value) => write!(w, "{:?}", value),
+        }
+    }
+
+    fn identifier(&self) -> Option<&str> {
+        match *self {
+            Fused::Token(ref t) => t.identifier(),
+            Fused::Strings(..) => None,
+        }
+    }
+
+    fn category(&self) -> Category {
+        match *self {
+            Fused::Token(ref t) => t.category(),
+            Fused::Strings(..) => Category::Literal,
+        }
+    }
+
+    fn descriptor(&self) -> &'static str {
+        match *self {
+            Fused::Token(ref t) => t.descriptor(),
+            Fused::Strings(descriptor, _) => descriptor,
+        }
+    }
+}
+
+/// Token which is kept as is by `FuseStrings`.
+fn unfused<T>(meta: TokenMeta<T>) -> TokenMeta<Fused<T>> {
+    meta.map(Fused::Token)
+}
+
+pub struct FuseStrings<'a, I, T, F> {
+    inner: I,
+    value: F,
+    /// Item which ended the last run of strings.
+    pending: Option<MetaResult<'a, T>>,
+}
+
+impl<'a, I, T, F> Iterator for FuseStrings<'a, I, T, F>
+where
+    I: MetaIter<'a, T>,
+    T: Token<'a>,
+    F: FnMut(&T) -> Option<Cow<'a, str>>,
+{
+    type Item = MetaResult<'a, Fused<T>>;
+
+    fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+        let first = match self.pending.take().or_else(|| self.inner.next())? {
+            Ok(meta) => meta,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut fused = match (self.value)(&first.token) {
+            Some(value) => value.into_owned(),
+            None => return Some(Ok(unfused(first))),
+        };
+        let (mut span, mut position, mut count) = (first.span, first.position, 1);
+        for next in self.inner.by_ref() {
+            match next {
+                Ok(meta) => match (self.value)(&meta.token) {
+                    Some(value) => {
+                        fused += &value;
+                        span.end = meta.span.end;
+                        position.end = meta.position.end;
+                        count += 1;
+                    }
+                    None => {
+                        self.pending = Some(Ok(meta));
+                        break;
+                    }
+                },
+                Err(e) => {
+                    self.pending = Some(Err(e));
+                    break;
+                }
+            }
+        }
+        if count == 1 {
+            return Some(Ok(unfused(first)));
+        }
+        Some(Ok(TokenMeta {
+            span,
+            position,
+            token: Fused::Strings(first.token.descriptor(), fused),
+            implicit: false,
+            newline: first.newline,
+            captures: Vec::new(),
+        }))
+    }
+}
+
+pub struct CoalesceErrors<'a, I, T> {
+    inner: I,
+    /// Item which ended the last run of errors.
+    pending: Option<MetaResult<'a, T>>,
+}
+
+impl<'a, I, T> Iterator for CoalesceErrors<'a, I, T>
+where
+    I: MetaIter<'a, T>,
+    T: Token<'a>,
+{
+    type Item = MetaResult<'a, T>;
+
+    fn next(&mut self) -> Option<<Self as Iterator>::Item> {
+        let mut error = match self.pending.take().or_else(|| self.inner.next())? {
+            Ok(meta) => return Some(Ok(meta)),
+            Err(e) => e,
+        };
+        for next in self.inner.by_ref() {
+            match next {
+                Err(e) if e.span.range().start == error.span.range().end => {
+                    error.span.end = e.span.end;
+                }
+                next => {
+                    self.pending = Some(next);
+                    break;
+                }
+            }
+        }
+        Some(Err(error))
+    }
+}
+
+pub struct TokensRaw<I, T> {
+    inner: I,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T, I> Iterator for TokensRaw<I, T>
+where
+    I: MetaIter<'a, T>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        match self.inner.next() {
+            Some(Ok(TokenMeta { token, .. })) => Some(token),
+            _ => None,
+        }
+    }
+}
+
+pub struct TokensPeekable<'a, I, T> {
+    inner: I,
+    /// `None` if nothing was peeked yet, `Some(None)` if inner stream has ended.
+    peeked: Option<Option<MetaResult<'a, T>>>,
+}
+
+impl<'a, I, T> TokensPeekable<'a, I, T>
+where
+    I: MetaIter<'a, T>,
+    T: Token<'a>,
+{
+    /// Look at the next item without consuming it.
+    pub fn peek(&mut self) -> Option<&MetaResult<'a, T>> {
+        let inner = &mut self.inner;
+        self.peeked.get_or_insert_with(|| inner.next()).as_ref()
+    }
+}
+
+impl<'a, I, T> Iterator for TokensPeekable<'a, I, T>
+where
+    I: MetaIter<'a, T>,
+    T: Token<'a>,
+{
+    type Item = MetaResult<'a, T>;
+
+    fn next(&mut self) -> Option<MetaResult<'a, T>> {
+        match self.peeked.take() {
+            Some(peeked) => peeked,
+            None => self.inner.next(),
+        }
+    }
+}
+
+pub struct LexerBuilder<'a, T> {
+    pairs: Vec<(Regex, Rule<'a, T>)>,
+    /// Priority of each rule in `pairs`, see `add_with_priority`.
+    priorities: Vec<i32>,
+    skip_whitespaces: fn(&'a str) -> &'a str,
+    longest_match: bool,
+    combined: bool,
+    error_recovery: bool,
+    max_tokens: Option<usize>,
+    keep_captures: bool,
+    skip_shebang: bool,
+    tab_width: usize,
+    /// The first pattern which failed to compile, if any.
+    error: Option<BuildError>,
+}
+
+impl<'a, T> LexerBuilder<'a, T>
+where
+    T: Token<'a>,
+{
+    pub fn new() -> Self {
+        LexerBuilder {
+            pairs: Vec::new(),
+            priorities: Vec::new(),
+            skip_whitespaces: |x| x,
+            longest_match: false,
+            combined: true,
+            error_recovery: false,
+            max_tokens: None,
+            keep_captures: false,
+            skip_shebang: false,
+            tab_width: 1,
+            error: None,
+        }
+    }
+
+    /// Add rule for a keyword, e.g. `if`, which only matches as a whole word, so that `iffy`
+    /// is left for other rules (such as identifiers).
+    ///
+    /// The `word` is taken literally, not as a regex.
+    pub fn add_keyword(self, word: &str, token: T) -> Self
+    where
+        T: Clone + 'a,
+    {
+        let regex = format!(r"\b{}\b", regex::escape(word));
+        let factory: Box<dyn TokenFactory<'a, T> + 'a> = Box::new(move |_| token.clone());
+        self.add_rule(&regex, false, Rule::Captures(factory))
+    }
+
+    /// Add rules for a whole set of operators, e.g. of a DSL, which are taken literally, not as
+    /// regexes.
+    ///
+    /// Longer operators are added first, so that `<<=` is not lexed as `<<` and `=`, whatever the
+    /// order of `ops` is.
+    pub fn add_operators(mut self, ops: &[(&str, T)]) -> Self
+    where
+        T: Clone + 'a,
+    {
+        let mut ops: Vec<_> = ops.iter().collect();
+        ops.sort_by_key(|&&(op, _)| Reverse(op.len()));
+        for &&(op, ref token) in ops.iter() {
+            let token = token.clone();
+            let factory: Box<dyn TokenFactory<'a, T> + 'a> = Box::new(move |_| token.clone());
+            self = self.add_rule(&regex::escape(op), false, Rule::Captures(factory));
+        }
+        self
+    }
+
+    /// Shortcut for `add_pair`.
+    ///
+    /// Invalid patterns are not reported immediately, but rather make `build` fail.
+    pub fn add<F>(self, regex: &str, factory: F) -> Self
+    where
+        F: Fn(Captures<'a>) -> T + 'static,
+    {
+        self.add_rule(regex, false, Rule::Captures(Box::new(factory)))
     }
 
-    /// Shortcut for `add_pair`.
-    pub fn add<F>(self, regex: &str, factory: F) -> Self
+    /// Same as `add`, but the rule matches regardless of case, e.g. `select` matches `SELECT`.
+    pub fn add_ci<F>(self, regex: &str, factory: F) -> Self
     where
         F: Fn(Captures<'a>) -> T + 'static,
     {
-        assert!(regex.len() > 0);
+        self.add_rule(regex, true, Rule::Captures(Box::new(factory)))
+    }
+
+    /// Same as `add`, but takes factory which is already boxed, e.g. chosen at runtime.
+    ///
+    /// This allows to build lexers out of data, such as a list of patterns and token kinds
+    /// read from a configuration file.
+    pub fn add_boxed(self, regex: &str, factory: Box<dyn TokenFactory<'a, T>>) -> Self {
+        self.add_rule(regex, false, Rule::Captures(factory))
+    }
 
-        let regex = match regex.chars().next().unwrap() {
-            '^' => Regex::new(regex),
-            _ => Regex::new(&format!("^{}", regex)),
-        }.expect("Invalid Regex!");
+    fn add_rule(mut self, regex: &str, case_insensitive: bool, rule: Rule<'a, T>) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
 
-        let factory = Box::new(factory);
+        let flags = if case_insensitive { "(?i)" } else { "" };
+        let compiled = match regex.chars().next() {
+            None => Err(BuildError::new(regex, "Pattern is empty")),
+            Some(c) => {
+                let unanchored = if c == '^' { &regex[1..] } else { regex };
+                Regex::new(&format!("^{}{}", flags, unanchored))
+                    .map_err(|e| BuildError::new(regex, e))
+            }
+        };
 
-        self.add_pair(regex, factory)
+        match compiled {
+            Ok(regex) => {
+                self.pairs.push((regex, rule));
+                self.priorities.push(0);
+            }
+            Err(error) => self.error = Some(error),
+        }
+        self
     }
 
     pub fn add_pair(mut self, regex: Regex, factory: Box<TokenFactory<'a, T>>) -> Self {
         assert_eq!('^', regex.as_str().chars().next().unwrap_or('\0'));
-        self.pairs.push((regex, factory));
+        self.pairs.push((regex, Rule::Captures(factory)));
+        self.priorities.push(0);
         self
     }
 
+    /// Same as `add`, but with explicit `priority` of the rule, so that it does not depend on
+    /// where the rule is added.
+    ///
+    /// Rules are tried in descending order of priority, and rules of the same priority in order
+    /// they were added. Rules added by other methods have priority `0`.
+    pub fn add_with_priority<F>(self, regex: &str, factory: F, priority: i32) -> Self
+    where
+        F: Fn(Captures<'a>) -> T + 'static,
+    {
+        let mut this = self.add(regex, factory);
+        if this.error.is_none() {
+            *this.priorities.last_mut().unwrap() = priority;
+        }
+        this
+    }
+
+    /// Add rule for tokens which regular expressions can not describe, e.g. nested comments.
+    ///
+    /// The `regex` only tells where such a token starts, e.g. `/\*`. Once it matches, `scanner`
+    /// is given the rest of the source, and returns length of the lexeme in bytes, or `None` if
+    /// there is no token after all. The lexeme is then converted into a token by `factory`.
+    ///
+    /// Scanner rules take part in the choice between rules the same way as other rules do.
+    pub fn add_scanner<S, F>(self, regex: &str, scanner: S, factory: F) -> Self
+    where
+        S: Fn(&'a str) -> Option<usize> + 'static,
+        F: Fn(&'a str) -> T + 'static,
+    {
+        self.add_rule(regex, false, Rule::Scanner(Box::new(scanner), Box::new(factory)))
+    }
+
+    /// Add rule for identifiers which start with a character for which `start` holds, and go on
+    /// with characters for which `cont` holds, e.g. `char::is_alphabetic` and `|c| c == '-' ||
+    /// c.is_alphanumeric()` for Lisp-style identifiers.
+    ///
+    /// Identifiers are scanned by hand rather than matched by a regex, see `add_scanner`.
+    pub fn identifiers<F>(self, start: fn(char) -> bool, cont: fn(char) -> bool, factory: F) -> Self
+    where
+        F: Fn(&'a str) -> T + 'static,
+    {
+        let scanner = move |source: &'a str| {
+            let mut chars = source.char_indices();
+            match chars.next() {
+                Some((_, c)) if start(c) => {}
+                _ => return None,
+            }
+            let end = chars.find(|&(_, c)| !cont(c)).map(|(i, _)| i);
+            Some(end.unwrap_or(source.len()))
+        };
+        self.add_scanner(r"(?s).", scanner, factory)
+    }
+
     /// Set up rule (function) to skip whitespaces before parsing each token.
+    ///
+    /// Skipped whitespaces are not lost for good: `Lexer::lossless_tokens` yields them as trivia.
     pub fn skip_whitespaces(mut self, f: fn(&'a str) -> &'a str) -> Self {
         self.skip_whitespaces = f;
         self
     }
 
-    pub fn build(self) -> Lexer<'a, T> {
-        Lexer {
-            pairs: Rc::new(self.pairs),
+    /// Choose between rules which match at the same position.
+    ///
+    /// By default (`false`) rules are tried in order they were added, and the first match wins.
+    /// When enabled, all rules are tried and the longest match wins, while ties are still broken
+    /// by the order of rules.
+    pub fn longest_match(mut self, enabled: bool) -> Self {
+        self.longest_match = enabled;
+        self
+    }
+
+    /// Find matching rules in one pass over all of them combined (default), rather than by
+    /// trying each rule in turn.
+    ///
+    /// Tokens are the same either way, only performance differs.
+    pub fn combined(mut self, enabled: bool) -> Self {
+        self.combined = enabled;
+        self
+    }
+
+    /// What to do when no rule matches.
+    ///
+    /// By default (`false`) token streams yield an error and end. When enabled, they yield an
+    /// error spanning the offending character, skip it, and go on lexing right after it.
+    pub fn error_recovery(mut self, enabled: bool) -> Self {
+        self.error_recovery = enabled;
+        self
+    }
+
+    /// Limit number of tokens per stream, e.g. to guard against pathological inputs.
+    ///
+    /// Once `n` tokens are yielded, a stream yields an error in place of the next token (if
+    /// there is any), and ends. Errors do not count as tokens.
+    pub fn max_tokens(mut self, n: usize) -> Self {
+        self.max_tokens = Some(n);
+        self
+    }
+
+    /// Record byte ranges of capture groups in `TokenMeta::captures`, e.g. for tools which need
+    /// both the name of a non-terminal and its span with angle brackets, without running regexes
+    /// again. Disabled by default.
+    pub fn keep_captures(mut self, enabled: bool) -> Self {
+        self.keep_captures = enabled;
+        self
+    }
+
+    /// Skip the first line of a source if it starts with `#!`, e.g. `#!/usr/bin/env bnf`.
+    ///
+    /// Only a shebang at the very start (after a byte order mark, if any) is skipped, so `#`
+    /// elsewhere is left to rules. Disabled by default.
+    pub fn skip_shebang(mut self, enabled: bool) -> Self {
+        self.skip_shebang = enabled;
+        self
+    }
+
+    /// Advance the column of `TokenMeta::position` after a tab to the next tab stop, i.e. to the
+    /// next multiple of `n` plus one, e.g. `8` for columns as displayed by terminals.
+    ///
+    /// Default is `1`, so that a tab is one column as any other character. Byte spans are not
+    /// affected.
+    ///
+    /// # Panics
+    ///
+    /// If `n` is zero.
+    pub fn tab_width(mut self, n: usize) -> Self {
+        assert!(n > 0, "tab width must be positive");
+        self.tab_width = n;
+        self
+    }
+
+    /// # Returns
+    ///
+    /// Lexer, or an error naming the first pattern which failed to compile.
+    pub fn build(self) -> Result<Lexer<'a, T>, BuildError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let mut pairs = self.pairs;
+        if self.priorities.iter().any(|&priority| priority != 0) {
+            // stable sort keeps rules of the same priority in order they were added
+            let mut ranked: Vec<_> = self.priorities.into_iter().zip(pairs).collect();
+            ranked.sort_by_key(|&(priority, _)| Reverse(priority));
+            pairs = ranked.into_iter().map(|(_, pair)| pair).collect();
+        }
+        let set = if self.combined {
+            let patterns = pairs.iter().map(|(regex, _)| regex.as_str());
+            let set = RegexSet::new(patterns).map_err(|e| BuildError::new("<all rules>", e))?;
+            Some(Rc::new(set))
+        } else {
+            None
+        };
+        Ok(Lexer {
+            pairs: Rc::new(pairs),
+            set,
             skip_whitespaces: self.skip_whitespaces,
+            longest_match: self.longest_match,
+            error_recovery: self.error_recovery,
+            max_tokens: self.max_tokens,
+            keep_captures: self.keep_captures,
+            skip_shebang: self.skip_shebang,
+            tab_width: self.tab_width,
+        })
+    }
+}
+
+/// Lexer rule could not be added because of invalid regular expression.
+#[derive(Clone, Debug)]
+pub struct BuildError {
+    /// Pattern as it was passed to `LexerBuilder::add`.
+    pub pattern: String,
+    pub description: String,
+}
+
+impl BuildError {
+    fn new<S: ToString>(pattern: &str, description: S) -> Self {
+        BuildError {
+            pattern: pattern.to_owned(),
+            description: description.to_string(),
         }
     }
 }
 
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        writeln!(f, "Invalid lexer rule {:?}:", self.pattern)?;
+        write!(f, "{}", self.description)
+    }
+}
+
+#[cfg(feature = "std")]
+impl ::std::error::Error for BuildError {}
+
 pub trait Token<'a>: Ord + Debug + Sized {
     /// Pretty-print token. Fallbacks to `Debug` implementation.
     fn describe(&self) -> String {
         format!("{:?}", self)
     }
 
+    /// Same as `describe`, but writes into `w`, e.g. to render many tokens into one buffer
+    /// without allocating a string for each of them.
+    ///
+    /// Falls back to `describe`, so implement both, or neither.
+    fn describe_into<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        w.write_str(&self.describe())
+    }
+
+    /// Name of an identifier-like token, e.g. of a variable or a non-terminal, for
+    /// `TokensExt::intern`. None by default.
+    fn identifier(&self) -> Option<&str> {
+        None
+    }
+
+    /// Highlight category of a token, e.g. for syntax highlighters. `Punctuation` by default,
+    /// i.e. not highlighted.
+    fn category(&self) -> Category {
+        Category::Punctuation
+    }
+
     /// Generic content-agnostic descriptor of a token's kind.
     ///
     /// Lifetime is intentionally `'static`, so that set of all possible descriptors must be known
@@ -340,10 +2161,38 @@ pub trait Token<'a>: Ord + Debug + Sized {
     fn descriptor(&self) -> &'static str;
 }
 
+/// Highlight category of a token, see `Token::category`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub enum Category {
+    Keyword,
+    Operator,
+    Literal,
+    Comment,
+    Identifier,
+    /// Brackets, delimiters and the like, as well as anything else.
+    Punctuation,
+}
+
+/// How a lexer rule makes a token once its regex matches.
+enum Rule<'a, T> {
+    /// Token is made out of regex captures.
+    Captures(Box<dyn TokenFactory<'a, T> + 'a>),
+    /// Regex only marks the start of a token, which is measured by a scanner, see
+    /// `LexerBuilder::add_scanner`.
+    Scanner(Box<dyn Fn(&'a str) -> Option<usize>>, Box<dyn Fn(&'a str) -> T>),
+}
+
+/// Conversion of regex captures of a matched rule into a token.
+///
+/// Implemented for any `Fn(Captures) -> T`, so closures passed to `LexerBuilder::add` are
+/// factories as they are. Captures are anchored at the start of the unlexed source, and group 0
+/// is the whole lexeme.
 pub trait TokenFactory<'a, T>
 where
     T: Token<'a>,
 {
+    /// Make token out of captures of the rule's regex.
     fn token(&self, c: Captures<'a>) -> T;
 }
 
@@ -384,6 +2233,7 @@ pub trait Metrics: Copy + Clone + Debug + Default + Ord + PartialOrd + Eq + Part
     }
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Debug, Default, Ord, PartialOrd, Eq, PartialEq)]
 pub struct Bytes;
 
@@ -438,14 +2288,14 @@ impl Metrics for Bytes {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Debug, Default, Ord, PartialOrd, Eq, PartialEq)]
 pub struct Chars;
 
 impl Chars {
     fn is_newline(c: char) -> bool {
-        let c = c as u8;
-        return c == 0x0d  // carriage returns (U+000D)
-            || c == 0x0a; // newlines (U+000A)
+        c == '\u{0d}' // carriage returns (U+000D)
+            || c == '\u{0a}' // newlines (U+000A)
     }
 }
 
@@ -494,6 +2344,7 @@ impl Metrics for Chars {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Debug)]
 pub struct Location<M: Metrics> {
     /// Line in source file, starting from 1.
@@ -608,6 +2459,7 @@ impl<M: Metrics, S: AsRef<str>> ::std::ops::AddAssign<S> for Location<M> {
 ///
 /// - `end` location must be no less than `start` location.
 /// - If end location is equal to start location, then span length is 1.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
 pub struct Span<M: Metrics> {
     pub start: Location<M>,
@@ -763,12 +2615,83 @@ impl<M: Metrics> Span<M> {
     }
 }
 
+impl Span<Bytes> {
+    /// Half-open range of byte offsets, suitable for slicing source string directly.
+    ///
+    /// ```rust
+    /// let text = &source[span.range()];
+    /// ```
+    pub fn range(&self) -> Range<usize> {
+        self.start.absolute..self.end.absolute + 1
+    }
+}
+
 pub type LinesWithSpans<'a, M> = Vec<(&'a str, Span<M>)>;
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TokenMeta<T> {
     pub span: Span<Bytes>,
+    /// Same as `span`, but measured in characters rather than bytes.
+    ///
+    /// Use it for human-readable line and column numbers.
+    pub position: Span<Chars>,
     pub token: T,
     pub implicit: bool,
+    /// Whether whitespaces skipped right before this token contained a newline.
+    ///
+    /// For the first token it tells about whitespaces at the beginning of the source.
+    pub newline: bool,
+    /// Byte ranges in the original source of the capture groups of the rule which made this
+    /// token, indexed as in the rule's regex: group 0 is the whole lexeme, and groups which did
+    /// not participate in the match are `None`.
+    ///
+    /// Empty unless enabled by `LexerBuilder::keep_captures`, and for tokens not made by rules.
+    pub captures: Captured,
+}
+
+impl<T> TokenMeta<T> {
+    /// Same meta with the token replaced by `f` of it, e.g. by a wrapper.
+    pub fn map<U, F>(self, f: F) -> TokenMeta<U>
+    where
+        F: FnOnce(T) -> U,
+    {
+        TokenMeta {
+            span: self.span,
+            position: self.position,
+            token: f(self.token),
+            implicit: self.implicit,
+            newline: self.newline,
+            captures: self.captures,
+        }
+    }
+
+    /// Byte offsets of the token in the original source.
+    ///
+    /// Shortcut to `self.span.range()`.
+    pub fn range(&self) -> Range<usize> {
+        self.span.range()
+    }
+
+    /// Line of the first character of the token, starting from 1.
+    pub fn line(&self) -> usize {
+        self.position.start.line
+    }
+
+    /// Column of the first character of the token, starting from 1.
+    ///
+    /// Multi-byte characters count as one column each.
+    pub fn column(&self) -> usize {
+        self.position.start.column
+    }
+
+    /// Compare the token only, regardless of its span and flags, e.g. in tests which should not
+    /// break when positions change.
+    pub fn eq_token(&self, other: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        self.token == *other
+    }
 }
 
 impl<T> Clone for TokenMeta<T>
@@ -778,8 +2701,11 @@ where
     fn clone(&self) -> Self {
         Self {
             span: self.span,
+            position: self.position,
             token: self.token.clone(),
             implicit: self.implicit,
+            newline: self.newline,
+            captures: self.captures.clone(),
         }
     }
 }
@@ -791,8 +2717,8 @@ where
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         write!(
             f,
-            "TokenMeta {{ span: {:?}, token: {:?}, implicit: {} }}",
-            self.span, self.token, self.implicit
+            "TokenMeta {{ span: {:?}, position: {:?}, token: {:?}, implicit: {}, newline: {} }}",
+            self.span, self.position, self.token, self.implicit, self.newline
         )
     }
 }
@@ -869,6 +2795,20 @@ where
         self.description = Some(description.into());
         self
     }
+
+    /// Render error as `Display` does, i.e. with the offending lines and carets under the span,
+    /// but against the given `source`.
+    ///
+    /// Errors of `ReaderTokens` do not keep the source, so it has to be supplied this way.
+    pub fn render(&self, source: &str) -> String {
+        Error {
+            filename: self.filename.clone(),
+            source,
+            span: self.span,
+            description: self.description.clone(),
+        }
+        .to_string()
+    }
 }
 
 impl<'a, M> From<SimpleError<M>> for Error<'a, M>
@@ -965,6 +2905,7 @@ where
     }
 }
 
+/// Whether `c` is one of ASCII whitespaces: space, horizontal tab, carriage return or newline.
 pub fn is_whitespace(c: char) -> bool {
     return c == 0x20 as char  // spaces (U+0020)
         || c == 0x09 as char  // horizontal tabs (U+0009)
@@ -972,7 +2913,10 @@ pub fn is_whitespace(c: char) -> bool {
         || c == 0x0a as char; // newlines (U+000A)
 }
 
-pub fn whitespace_filter(source: &str) -> &str {
+/// Skip leading whitespaces (see `is_whitespace`), including newlines.
+///
+/// Suits `LexerBuilder::skip_whitespaces` of most languages.
+pub fn ascii_whitespace_filter(source: &str) -> &str {
     for (i, c) in source.char_indices() {
         if !is_whitespace(c) {
             return &source[i..];
@@ -981,6 +2925,48 @@ pub fn whitespace_filter(source: &str) -> &str {
     &source[source.len()..]
 }
 
+/// Readable difference between `expected` and `actual` tokens, e.g. for failures of tests over
+/// long token tables.
+///
+/// Lists tokens around the first mismatch, one per line with its index: expected ones are
+/// marked with `-`, actual ones with `+`, and equal ones are not marked.
+///
+/// # Returns
+///
+/// Empty string if tokens are equal.
+pub fn diff<T>(expected: &[T], actual: &[T]) -> String
+where
+    T: Debug + PartialEq,
+{
+    const CONTEXT: usize = 2;
+
+    let len = expected.len().max(actual.len());
+    let first = match (0..len).find(|&i| expected.get(i) != actual.get(i)) {
+        Some(first) => first,
+        None => return String::new(),
+    };
+    let mut diff = format!(
+        "First mismatch at index {} (expected {} tokens, found {}):\n",
+        first,
+        expected.len(),
+        actual.len(),
+    );
+    for i in first.saturating_sub(CONTEXT)..len.min(first + CONTEXT + 1) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) if e == a => diff += &format!("  {:>4} {:?}\n", i, e),
+            (e, a) => {
+                if let Some(e) = e {
+                    diff += &format!("- {:>4} {:?}\n", i, e);
+                }
+                if let Some(a) = a {
+                    diff += &format!("+ {:>4} {:?}\n", i, a);
+                }
+            }
+        }
+    }
+    diff
+}
+
 /// Little helper for tests.
 #[cfg(test)]
 pub fn token<'a, T: Token<'a>>(x: Option<Result<TokenMeta<T>, Error<'a, Bytes>>>) -> T {
@@ -1156,8 +3142,14 @@ mod test {
                 start: Location::new(1, 1, 0),
                 end: Location::new(2, 1, 5),
             },
+            position: Span {
+                start: Location::new(1, 1, 0),
+                end: Location::new(2, 1, 5),
+            },
             token: Tok,
             implicit: false,
+            newline: false,
+            captures: Vec::new(),
         };
     }
 
@@ -1223,6 +3215,524 @@ Custom error message.
         assert_eq!(expected.to_owned(), format!("{}", error));
     }
 
+    #[test]
+    fn test_error_render() {
+        use lang::bnf::{make_lexer, BnfLexer};
+        use std::io::Cursor;
+
+        const SOURCE: &str = "<A> ::= <B>\n  | ? ;";
+
+        let error = BnfLexer::default()
+            .tokens_from_reader(Cursor::new(SOURCE), "test.bnf".into())
+            .find_map(Result::err)
+            .unwrap();
+        assert!(error.source.is_empty());
+
+        let rendered = error.render(SOURCE);
+        assert!(rendered.starts_with("Error at test.bnf:2:5\n2   |   | ? ;\n    |     ^\n"));
+        assert!(rendered.contains("No token could be matched at byte 16"));
+
+        // same as if the error kept its source
+        let error = make_lexer()
+            .into_tokens(SOURCE, "test.bnf".into())
+            .find_map(Result::err)
+            .unwrap();
+        assert_eq!(error.to_string(), rendered);
+    }
+
+    #[test]
+    fn test_token_range() {
+        use lang::bnf::make_lexer;
+
+        let source = "<A> ::= <B>\n    | \"c\" <D> ;";
+        let tokens = make_lexer()
+            .into_tokens(source, "test.bnf".into())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(7, tokens.len());
+        for meta in tokens {
+            assert_eq!(&source[meta.range()], meta.token.describe());
+        }
+    }
+
+    #[test]
+    fn test_token_position() {
+        use lang::ebnf::{make_lexer, EbnfOperator, EbnfToken};
+
+        let source = "<Α> /* multi\n   line */ ::= \"β\"\n    | <Γ> ;";
+        let tokens = make_lexer()
+            .into_tokens(source, "test.bnf".into())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let positions = tokens
+            .iter()
+            .map(|meta| (meta.token, meta.line(), meta.column()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            [
+                (EbnfToken::NonTerminal("Α"), 1, 1),
+                (EbnfToken::Comment(" multi\n   line "), 1, 5),
+                (EbnfToken::Operator(EbnfOperator::Def), 2, 12),
+                (EbnfToken::Terminal("β"), 2, 16),
+                (EbnfToken::Operator(EbnfOperator::Alt), 3, 5),
+                (EbnfToken::NonTerminal("Γ"), 3, 7),
+                (EbnfToken::Delimiter, 3, 11),
+            ]
+        );
+
+        // Bytes still count towards `span`.
+        assert_eq!(4, tokens[0].span.end.column);
+        assert_eq!(3, tokens[0].position.end.column);
+    }
+
+    #[test]
+    fn test_peekable() {
+        use lang::bnf::make_lexer;
+
+        let source = "<A> ::= <B> | \"c\" <D> ;";
+        let mut tokens = make_lexer()
+            .into_tokens(source, "test.bnf".into())
+            .into_peekable();
+
+        let mut count = 0;
+        loop {
+            let peeked = tokens.peek().cloned();
+            // peeking twice is the same as peeking once
+            assert_eq!(
+                peeked.as_ref().map(|meta| meta.as_ref().unwrap().token),
+                tokens.peek().map(|meta| meta.as_ref().unwrap().token)
+            );
+            let next = tokens.next();
+            match (peeked, next) {
+                (Some(Ok(peeked)), Some(Ok(next))) => {
+                    assert_eq!(peeked.token, next.token);
+                    assert_eq!(peeked.span, next.span);
+                    count += 1;
+                }
+                (None, None) => break,
+                (peeked, next) => panic!("{:?} != {:?}", peeked, next),
+            }
+        }
+        assert_eq!(7, count);
+        assert!(tokens.peek().is_none());
+    }
+
+    #[test]
+    fn test_peekable_composes() {
+        use lang::ebnf::{drop_comments, make_lexer, EbnfOperator, EbnfToken};
+
+        let source = "<A> // comment\n ::= <B> ;";
+        let mut tokens = drop_comments(make_lexer().into_tokens(source, "test.bnf".into()))
+            .into_peekable();
+        assert_eq!(
+            EbnfToken::NonTerminal("A"),
+            tokens.peek().unwrap().as_ref().unwrap().token
+        );
+
+        let raw = tokens.into_raw().collect::<Vec<_>>();
+        assert_eq!(
+            raw,
+            [
+                EbnfToken::NonTerminal("A"),
+                EbnfToken::Operator(EbnfOperator::Def),
+                EbnfToken::NonTerminal("B"),
+                EbnfToken::Delimiter,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reader_split_chars() {
+        use lang::ebnf::{make_lexer, EbnfLexer};
+        use std::io::Cursor;
+
+        let source = "<α> ::= \"β\"\n  ; <γ> ! <δ>";
+        let lexer = make_lexer();
+        let expected: Vec<_> = lexer
+            .tokens(source, "test.bnf".into())
+            .map(|meta| match meta {
+                Ok(meta) => format!("{:?} {:?}", meta.span, meta.position),
+                Err(e) => format!("{:?} {:?}", e.span, e.description),
+            }).collect();
+        let tokens: Vec<_> = EbnfLexer
+            .tokens_from_reader(Cursor::new(source), "test.bnf".into())
+            .chunk_size(1)
+            .map(|meta| match meta {
+                Ok(meta) => format!("{:?} {:?}", meta.span, meta.position),
+                Err(e) => format!("{:?} {:?}", e.span, e.description),
+            }).collect();
+
+        assert_eq!(tokens, expected);
+        assert_eq!(tokens.len(), 6);
+    }
+
+    #[test]
+    fn test_reader_invalid_utf8() {
+        use lang::ebnf::EbnfLexer;
+        use std::io::Cursor;
+
+        let source: &[u8] = b"<A> \xff";
+        let mut tokens = EbnfLexer.tokens_from_reader(Cursor::new(source), "test.bnf".into());
+
+        assert!(tokens.next().unwrap().is_ok());
+        assert_eq!(tokens.next().unwrap().unwrap_err().span.start.absolute, 4);
+        assert!(tokens.next().is_none());
+    }
+
+    #[test]
+    fn test_reader_io_error() {
+        use lang::ebnf::EbnfLexer;
+        use std::io;
+
+        // reads the source once, then fails
+        struct Failing(Option<&'static [u8]>);
+        impl Read for Failing {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                match self.0.take() {
+                    Some(source) => {
+                        buf[..source.len()].copy_from_slice(source);
+                        Ok(source.len())
+                    }
+                    None => Err(io::Error::other("broken pipe")),
+                }
+            }
+        }
+
+        let mut tokens = EbnfLexer.tokens_from_reader(Failing(Some(b"<A> ")), "test.bnf".into());
+
+        assert_eq!(tokens.next().unwrap().unwrap().token.description, "<A>");
+        let error = tokens.next().unwrap().unwrap_err();
+        assert!(error.to_string().contains("broken pipe"));
+        assert!(tokens.next().is_none());
+    }
+
+    #[test]
+    fn test_reader_lexemes() {
+        use lang::bnf::BnfToken;
+        use std::cell::RefCell;
+
+        thread_local!(static SEEN: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) });
+
+        // rules of a family work for any lifetime of the source, so they can only keep owned
+        // copies of lexemes: pushing `name` itself into `SEEN` does not compile, as the chunk
+        // it borrows from is gone after the next read
+        struct Recording;
+        impl LexerFamily for Recording {
+            type Token<'a> = BnfToken<'a>;
+
+            fn lexer<'a>(&self) -> Lexer<'a, BnfToken<'a>> {
+                LexerBuilder::new()
+                    .skip_whitespaces(ascii_whitespace_filter)
+                    .add(r"<(.+?)>", |c| {
+                        let name = c.get(1).unwrap().as_str();
+                        SEEN.with(|seen| seen.borrow_mut().push(name.to_owned()));
+                        BnfToken::NonTerminal(name)
+                    }).build()
+                    .unwrap()
+            }
+        }
+
+        let tokens: Vec<_> = Recording
+            .tokens_from_reader("<A> <Bb> <Ccc>".as_bytes(), "test.bnf".into())
+            .chunk_size(1)
+            .map(|meta| meta.unwrap().token.description)
+            .collect();
+        assert_eq!(tokens, ["<A>", "<Bb>", "<Ccc>"]);
+
+        // lexemes at the end of a chunk are matched again once more input is read
+        let mut seen = SEEN.with(|seen| seen.borrow().clone());
+        seen.dedup();
+        assert_eq!(seen, ["A", "Bb", "Ccc"]);
+    }
+
+    #[test]
+    fn test_build_error() {
+        use lang::brainfuck::BfToken;
+
+        let error = LexerBuilder::new()
+            .add(r"\+", constant!(BfToken::Inc))
+            .add(r"(", constant!(BfToken::Cond))
+            .add(r"[", constant!(BfToken::Loop))
+            .build()
+            .err()
+            .unwrap();
+
+        // the first one is reported
+        assert_eq!("(", error.pattern);
+        assert!(format!("{}", error).starts_with("Invalid lexer rule \"(\":\n"));
+    }
+
+    #[test]
+    fn test_build_error_empty() {
+        use lang::brainfuck::BfToken;
+
+        let error = LexerBuilder::new()
+            .add("", constant!(BfToken::Inc))
+            .build()
+            .err()
+            .unwrap();
+
+        assert_eq!("", error.pattern);
+        assert_eq!("Pattern is empty", error.description);
+    }
+
+    #[test]
+    fn test_lexer_reuse() {
+        use lang::bnf::make_lexer;
+
+        let source = r#"<A> ::= <B> | "c" ;"#;
+        let lexer = make_lexer();
+        let first = lexer.tokens(source, "test.bnf".into()).into_raw().collect::<Vec<_>>();
+        let second = lexer.tokens(source, "test.bnf".into()).into_raw().collect::<Vec<_>>();
+        assert_eq!(first, second);
+
+        // streams do not interfere with each other
+        let interleaved = lexer
+            .tokens(source, "test.bnf".into())
+            .zip(lexer.clone().into_tokens(source, "test.bnf".into()))
+            .map(|(a, b)| (a.unwrap().token, b.unwrap().token))
+            .collect::<Vec<_>>();
+        assert_eq!(interleaved.len(), first.len());
+        assert!(interleaved.iter().all(|&(a, b)| a == b));
+    }
+
+    #[test]
+    fn test_add_ci() {
+        #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+        enum SqlToken<'a> {
+            Select,
+            Ident(&'a str),
+        }
+        impl<'a> Token<'a> for SqlToken<'a> {
+            fn descriptor(&self) -> &'static str {
+                match *self {
+                    SqlToken::Select => "SELECT",
+                    SqlToken::Ident(..) => "Ident",
+                }
+            }
+        }
+
+        let lexer = LexerBuilder::new()
+            .skip_whitespaces(ascii_whitespace_filter)
+            .add_ci(r"select\b", constant!(SqlToken::Select))
+            .add(r"[A-Za-z]+", |c| SqlToken::Ident(c.get(0).unwrap().as_str()))
+            .build()
+            .unwrap();
+        let tokens = lexer
+            .tokens("SELECT select Select selection", "test.sql".into())
+            .into_raw()
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tokens,
+            [
+                SqlToken::Select,
+                SqlToken::Select,
+                SqlToken::Select,
+                SqlToken::Ident("selection"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_boxed() {
+        #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+        struct Named<'a> {
+            kind: &'static str,
+            text: &'a str,
+        }
+        impl<'a> Token<'a> for Named<'a> {
+            fn descriptor(&self) -> &'static str {
+                self.kind
+            }
+        }
+
+        fn factory<'a>(kind: &'static str) -> Box<dyn TokenFactory<'a, Named<'a>>> {
+            Box::new(move |c: Captures<'a>| Named {
+                kind,
+                text: c.get(0).unwrap().as_str(),
+            })
+        }
+
+        // as if read from a configuration file
+        let config = [(r"[0-9]+", "Number"), (r"[a-z]+", "Word"), (r"\+", "Plus")];
+
+        let lexer = config
+            .iter()
+            .fold(LexerBuilder::new(), |builder, &(pattern, kind)| {
+                builder.add_boxed(pattern, factory(kind))
+            }).skip_whitespaces(ascii_whitespace_filter)
+            .build()
+            .unwrap();
+        let tokens = lexer
+            .tokens("x + 42", "test.cfg".into())
+            .into_raw()
+            .map(|t| (t.kind, t.text))
+            .collect::<Vec<_>>();
+
+        assert_eq!(tokens, [("Word", "x"), ("Plus", "+"), ("Number", "42")]);
+    }
+
+    #[test]
+    fn test_add_keyword() {
+        use lang::golang::{GoKeyword, GoToken::*};
+
+        let source = String::from("if iffy else _else");
+        let lexer = LexerBuilder::new()
+            .skip_whitespaces(ascii_whitespace_filter)
+            .add_keyword("if", Keyword(GoKeyword::If))
+            .add_keyword("else", Keyword(GoKeyword::Else))
+            .add(r"[a-z_]+", |c| Ident(c.get(0).unwrap().as_str()))
+            .build()
+            .unwrap();
+        let tokens: Vec<_> = lexer.tokens(&source, "test.go".into()).into_raw().collect();
+
+        assert_eq!(
+            tokens,
+            [
+                Keyword(GoKeyword::If),
+                Ident("iffy"),
+                Keyword(GoKeyword::Else),
+                Ident("_else"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        use lang::bnf::BnfToken::*;
+
+        let expected = [NonTerminal("A"), Delimiter, NonTerminal("B"), Terminal("c"), Delimiter];
+        let actual = [NonTerminal("A"), Delimiter, NonTerminal("B"), Terminal("x"), Delimiter];
+        assert_eq!(
+            diff(&expected, &actual),
+            concat!(
+                "First mismatch at index 3 (expected 5 tokens, found 5):\n",
+                "     1 Delimiter\n",
+                "     2 NonTerminal(\"B\")\n",
+                "-    3 Terminal(\"c\")\n",
+                "+    3 Terminal(\"x\")\n",
+                "     4 Delimiter\n",
+            )
+        );
+
+        // missing tokens at the end
+        let missing = diff(&expected, &actual[..1]);
+        assert!(missing.starts_with("First mismatch at index 1 (expected 5 tokens, found 1):\n"));
+        assert!(missing.ends_with("-    3 Terminal(\"c\")\n"));
+
+        assert_eq!(diff(&expected, &expected), "");
+    }
+
+    #[test]
+    fn test_add_with_priority() {
+        use lang::golang::{GoKeyword, GoToken::*};
+
+        let lexer = LexerBuilder::new()
+            .skip_whitespaces(ascii_whitespace_filter)
+            .add_with_priority(r".", |c| Ident(c.get(0).unwrap().as_str()), -1)
+            .add(r"else\b", constant!(Keyword(GoKeyword::Else)))
+            .add_with_priority(r"if\b", constant!(Keyword(GoKeyword::If)), 10)
+            .build()
+            .unwrap();
+        let tokens: Vec<_> = lexer.tokens("if x else", "test.go".into()).into_raw().collect();
+
+        assert_eq!(
+            tokens,
+            [Keyword(GoKeyword::If), Ident("x"), Keyword(GoKeyword::Else)]
+        );
+    }
+
+    #[test]
+    fn test_identifiers() {
+        use lang::golang::{GoOperator, GoToken::*};
+
+        let ascii = LexerBuilder::new()
+            .skip_whitespaces(ascii_whitespace_filter)
+            .identifiers(
+                |c| c == '_' || c.is_ascii_alphabetic(),
+                |c| c == '_' || c.is_ascii_alphanumeric(),
+                Ident,
+            ).add(r"-", constant!(Operator(GoOperator::Sub)))
+            .build()
+            .unwrap();
+        let tokens: Vec<_> = ascii.tokens("_a1 foo-bar", "test".into()).into_raw().collect();
+        assert_eq!(
+            tokens,
+            [Ident("_a1"), Ident("foo"), Operator(GoOperator::Sub), Ident("bar")]
+        );
+        assert!(ascii.tokens("1a", "test".into()).next().unwrap().is_err());
+        assert!(ascii.tokens("é", "test".into()).next().unwrap().is_err());
+
+        let lisp = LexerBuilder::new()
+            .skip_whitespaces(ascii_whitespace_filter)
+            .identifiers(char::is_alphabetic, |c| c == '-' || c.is_alphanumeric(), Ident)
+            .add(r"-", constant!(Operator(GoOperator::Sub)))
+            .build()
+            .unwrap();
+        let tokens: Vec<_> = lisp.tokens("foo-bar - é-2", "test".into()).into_raw().collect();
+        assert_eq!(tokens, [Ident("foo-bar"), Operator(GoOperator::Sub), Ident("é-2")]);
+    }
+
+    #[test]
+    fn test_add_operators() {
+        use lang::golang::{GoOperator::*, GoToken::Operator};
+
+        let lexer = LexerBuilder::new()
+            .skip_whitespaces(ascii_whitespace_filter)
+            .add_operators(&[
+                ("<", Operator(Lss)),
+                ("<<=", Operator(ShlAssign)),
+                ("<<", Operator(Shl)),
+                ("...", Operator(Ellipsis)),
+            ]).build()
+            .unwrap();
+        let tokens: Vec<_> = lexer.tokens("<<= << < ...", "test.go".into()).into_raw().collect();
+
+        assert_eq!(
+            tokens,
+            [Operator(ShlAssign), Operator(Shl), Operator(Lss), Operator(Ellipsis)]
+        );
+        // metacharacters are escaped
+        assert!(lexer.tokens("..", "test.go".into()).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_longest_match() {
+        use lang::golang::{GoOperator::*, GoToken::Operator};
+
+        let builder = || {
+            LexerBuilder::new()
+                .skip_whitespaces(ascii_whitespace_filter)
+                .add(r"<", constant!(Operator(Lss)))
+                .add(r"<<", constant!(Operator(Shl)))
+                .add(r"<<=", constant!(Operator(ShlAssign)))
+                .add(r"=", constant!(Operator(Assign)))
+                // never wins: same length as the first rule
+                .add(r"<", constant!(Operator(Gtr)))
+        };
+        let source = "<<= <";
+
+        let first = builder().build().unwrap();
+        let tokens = first.tokens(source, "test.go".into()).into_raw().collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            [
+                Operator(Lss),
+                Operator(Lss),
+                Operator(Assign),
+                Operator(Lss),
+            ]
+        );
+
+        let longest = builder().longest_match(true).build().unwrap();
+        let tokens = longest.tokens(source, "test.go".into()).into_raw().collect::<Vec<_>>();
+        assert_eq!(tokens, [Operator(ShlAssign), Operator(Lss)]);
+    }
+
     #[test]
     fn test_raw_iter() {
         use lang::brainfuck::{make_lexer, BfToken};
@@ -0,0 +1,60 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate id_tree;
+#[cfg(feature = "std")]
+extern crate ndarray;
+extern crate regex;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+#[cfg(feature = "std")]
+use lex::{MetaIter, Token};
+
+/// Parts of `std` which are there in `core` and `alloc`, so that `std::...` paths work without
+/// the `std` feature too.
+#[cfg(not(feature = "std"))]
+mod std {
+    pub use alloc::{borrow, collections, fmt, rc, vec};
+    pub use core::*;
+
+    pub mod prelude {
+        pub mod v1 {
+            pub use alloc::borrow::ToOwned;
+            pub use alloc::boxed::Box;
+            pub use alloc::string::{String, ToString};
+            pub use alloc::vec::Vec;
+            pub use core::prelude::v1::*;
+        }
+    }
+}
+
+// import macros before anything else
+pub mod macros;
+// ...
+pub mod lang;
+pub mod lex;
+#[cfg(feature = "std")]
+pub mod syn;
+#[cfg(feature = "std")]
+pub mod tree_util;
+
+/// Fancy tokens printer.
+#[cfg(feature = "std")]
+pub fn print_tokens<'a, T: Token<'a>, I: MetaIter<'a, T>>(tokens: I) {
+    println!("Tokens:");
+    for (i, t) in tokens.enumerate() {
+        match t {
+            Ok(meta) => println!("#{:02}: {}", i + 1, meta.token.describe()),
+            Err(error) => {
+                println!("{}", error);
+            }
+        }
+    }
+}
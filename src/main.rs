@@ -1,31 +1,8 @@
-extern crate id_tree;
-extern crate ndarray;
-extern crate regex;
+extern crate ha_2_lexer;
 
-use lex::{MetaIter, Token};
+use ha_2_lexer::syn;
 use std::io::Read;
 
-// import macros before anything else
-pub mod macros;
-// ...
-pub mod lang;
-pub mod lex;
-pub mod syn;
-pub mod tree_util;
-
-/// Fancy tokens printer.
-pub fn print_tokens<'a, T: Token<'a>, I: MetaIter<'a, T>>(tokens: I) {
-    println!("Tokens:");
-    for (i, t) in tokens.enumerate() {
-        match t {
-            Ok(meta) => println!("#{:02}: {}", i + 1, meta.token.describe()),
-            Err(error) => {
-                println!("{}", error);
-            }
-        }
-    }
-}
-
 fn read(now_what: &str) -> String {
     println!("Reading {} from stdin...", now_what);
     let mut stdin = std::io::stdin();
@@ -31,8 +31,9 @@
 #![allow(non_snake_case)]
 pub use self::GrammarSymbol::*;
 use lang::bnf::{make_lexer, BnfOperator, BnfToken};
-use lex::TokensExt;
-use std::collections::HashSet;
+use lex::{Error, ErrorBytes, Span, TokenMeta, TokensExt};
+use std::collections::{HashMap, HashSet};
+use std::mem;
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
 pub enum GrammarSymbol<'a> {
@@ -195,6 +196,67 @@ pub struct Grammar<'a, 'b> {
     pub rules: Vec<GrammarRule<'a, 'b>>,
 }
 
+/// Parse BNF `source` into a grammar.
+///
+/// Unlike `Grammar::from_str`, rules are not split by lines, but end with a delimiter (optional
+/// after the last rule), so that a rule may span several lines:
+///
+/// ```bnf
+/// <Grammar>      ::= <Rule> | <Rule> ";" <Grammar>
+/// <Rule>         ::= <NonTerminal> "::=" <Alternatives>
+/// <Alternatives> ::= <Sequence> | <Sequence> "|" <Alternatives>
+/// <Sequence>     ::= "" | <Symbol> <Sequence>
+/// ```
+///
+/// # Returns
+///
+/// Grammar, or the first lexer or syntax error.
+#[allow(clippy::result_large_err)]
+pub fn parse(source: &str, filename: String) -> Result<Grammar<'_, '_>, ErrorBytes<'_>> {
+    let expected = |span: Span<_>, what: &str| {
+        let description = format!("Expected {}.", what);
+        Error::new(filename.clone(), source, span, Some(description))
+    };
+    let mut tokens = make_lexer().into_tokens(source, filename.clone());
+    let mut rules = vec![];
+
+    while let Some(next) = tokens.next() {
+        let meta = next?;
+        let mut rule = match meta.token {
+            BnfToken::NonTerminal(name) => GrammarRule::new(name),
+            _ => return Err(expected(meta.span, "non-terminal")),
+        };
+        match tokens.next() {
+            Some(Ok(TokenMeta {
+                token: BnfToken::Operator(BnfOperator::Def),
+                ..
+            })) => {}
+            Some(Ok(meta)) => return Err(expected(meta.span, "::=")),
+            Some(Err(e)) => return Err(e),
+            None => return Err(expected(Default::default(), "::=")),
+        }
+
+        let mut sequence = vec![];
+        for next in tokens.by_ref() {
+            let meta = next?;
+            match meta.token {
+                BnfToken::Terminal(s) => sequence.push(Terminal(s)),
+                BnfToken::NonTerminal(s) => sequence.push(NonTerminal(s)),
+                BnfToken::Operator(BnfOperator::Alt) => {
+                    rule.expression.push(mem::take(&mut sequence));
+                }
+                BnfToken::Delimiter => break,
+                BnfToken::Operator(BnfOperator::Def) => {
+                    return Err(expected(meta.span, "symbol, | or ;"));
+                }
+            }
+        }
+        rule.expression.push(sequence);
+        rules.push(rule);
+    }
+    Ok(Grammar { rules })
+}
+
 pub fn non_empties<'a, S: AsRef<str>>(iter: impl Iterator<Item = S>) -> impl Iterator<Item = S> {
     iter.filter(|s| !s.as_ref().trim().is_empty())
 }
@@ -212,9 +274,31 @@ impl<'a, 'b> Grammar<'a, 'b> {
         Ok(Grammar { rules })
     }
 
+    /// FOLLOW set for a symbol.
+    ///
+    /// See `follow_guarded` for implementation details.
     pub fn follow(&self, token: GrammarSymbol, start_symbol: GrammarSymbol) -> HashSet<&'b str> {
+        self.follow_guarded(token, start_symbol, &mut hash_set!{})
+    }
+
+    /// FOLLOW set for a symbol.
+    ///
+    /// # Note
+    ///
+    /// Set `guard` is used internally to prevent infinite recursion of symbols cycles.
+    fn follow_guarded(
+        &self,
+        token: GrammarSymbol,
+        start_symbol: GrammarSymbol,
+        guard: &mut HashSet<String>,
+    ) -> HashSet<&'b str> {
         let mut set: HashSet<_> = hash_set!();
 
+        // Have we seen it before? `HashSet::insert` returns false when an item is already there.
+        if !guard.insert(token.to_str()) {
+            return set;
+        }
+
         if token == start_symbol {
             set.insert("$");
         }
@@ -238,7 +322,8 @@ impl<'a, 'b> Grammar<'a, 'b> {
                     }
 
                     if has_empty && (NonTerminal(rule.name) != token) {
-                        let follow_a = self.follow(NonTerminal(rule.name), start_symbol);
+                        let follow_a =
+                            self.follow_guarded(NonTerminal(rule.name), start_symbol, guard);
                         set.extend(follow_a.into_iter());
                     }
                 }
@@ -310,8 +395,9 @@ impl<'a, 'b> Grammar<'a, 'b> {
 
     /// FIRST set for sequence of productions.
     ///
-    /// First(e1 ... em) = { First(e1) U First(e2 ... em) | if e1 ==> Λ
-    ///                    { First(e1)                    | otherwise
+    /// First() = {Λ}
+    /// First(e1 ... em) = { First(e1) \ {Λ} U First(e2 ... em) | if e1 ==> Λ
+    ///                    { First(e1)                          | otherwise
     ///
     /// # Note
     ///
@@ -325,10 +411,11 @@ impl<'a, 'b> Grammar<'a, 'b> {
         I: Iterator<Item = GrammarSymbol<'b>>,
     {
         Ok(match A.next() {
-            None => hash_set!{},
+            None => hash_set!{ Epsilon::epsilon() },
             Some(e) => {
                 let mut set = self.first_unit(e, guard)?;
-                if set.contains(Epsilon::epsilon()) {
+                // Epsilon only survives if the rest of the sequence can vanish as well.
+                if set.remove(Epsilon::epsilon()) {
                     set.extend(self.first_production(A, guard)?);
                 }
                 set
@@ -336,6 +423,220 @@ impl<'a, 'b> Grammar<'a, 'b> {
         })
     }
 
+    /// Non-terminals which derive themselves as their leftmost symbol, either directly
+    /// (`<A> ::= <A> "x"`) or through other rules (`<A> ::= <B> "x"` and `<B> ::= <A>`).
+    ///
+    /// Symbols after the leftmost one count as leftmost too if all of the preceding ones may
+    /// vanish, e.g. `<A> ::= <B> <A>` is left recursive if `<B> ::= ""`.
+    ///
+    /// # Returns
+    ///
+    /// Names in order of their rules, without duplicates.
+    pub fn left_recursive(&self) -> Vec<String> {
+        // non-terminals which derive epsilon
+        let mut nullable: HashSet<&str> = hash_set!();
+        loop {
+            let before = nullable.len();
+            for rule in self.rules.iter() {
+                let vanishes = |symbol: &GrammarSymbol| match *symbol {
+                    NonTerminal(name) => nullable.contains(name),
+                    Terminal(_) => symbol.is_epsilon(),
+                };
+                if rule.expression.iter().any(|prod| prod.iter().all(vanishes)) {
+                    nullable.insert(rule.name);
+                }
+            }
+            if nullable.len() == before {
+                break;
+            }
+        }
+
+        // leftmost non-terminals of each rule's alternatives
+        let leftmost = |name: &str| -> Vec<&str> {
+            let mut symbols = vec![];
+            for rule in self.rules.iter().filter(|rule| rule.name == name) {
+                for prod in rule.expression.iter() {
+                    for symbol in prod.iter() {
+                        match *symbol {
+                            NonTerminal(next) => {
+                                symbols.push(next);
+                                if !nullable.contains(next) {
+                                    break;
+                                }
+                            }
+                            Terminal(_) if symbol.is_epsilon() => {}
+                            Terminal(_) => break,
+                        }
+                    }
+                }
+            }
+            symbols
+        };
+
+        let mut recursive: Vec<String> = vec![];
+        for rule in self.rules.iter() {
+            if recursive.iter().any(|name| name == rule.name) {
+                continue;
+            }
+            // depth-first search for a path back to the rule
+            let mut seen: HashSet<&str> = hash_set!();
+            let mut stack = leftmost(rule.name);
+            while let Some(name) = stack.pop() {
+                if name == rule.name {
+                    recursive.push(name.to_owned());
+                    break;
+                }
+                if seen.insert(name) {
+                    stack.extend(leftmost(name));
+                }
+            }
+        }
+        recursive
+    }
+
+    /// Non-terminals which are defined, but can not be derived from the `start` one.
+    ///
+    /// # Returns
+    ///
+    /// Names in order of their rules, without duplicates.
+    pub fn unreachable(&self, start: &str) -> Vec<String> {
+        let mut reachable: HashSet<&str> = hash_set!();
+        let mut stack = vec![start];
+        while let Some(name) = stack.pop() {
+            if !reachable.insert(name) {
+                continue;
+            }
+            for rule in self.rules.iter().filter(|rule| rule.name == name) {
+                let symbols = rule.expression.iter().flat_map(|prod| prod.iter());
+                stack.extend(symbols.filter_map(|symbol| match *symbol {
+                    NonTerminal(next) => Some(next),
+                    Terminal(_) => None,
+                }));
+            }
+        }
+
+        let mut unreachable: Vec<String> = vec![];
+        for rule in self.rules.iter() {
+            if !reachable.contains(rule.name) && !unreachable.iter().any(|n| n == rule.name) {
+                unreachable.push(rule.name.to_owned());
+            }
+        }
+        unreachable
+    }
+
+    /// Non-terminals which are referenced, but never defined.
+    ///
+    /// # Returns
+    ///
+    /// Names in order of their first reference, without duplicates.
+    pub fn undefined(&self) -> Vec<String> {
+        let mut undefined: Vec<String> = vec![];
+        let symbols = self
+            .rules
+            .iter()
+            .flat_map(|rule| rule.expression.iter())
+            .flat_map(|prod| prod.iter());
+        for symbol in symbols {
+            if let NonTerminal(name) = *symbol {
+                if self.get_rule(name).is_none() && !undefined.iter().any(|n| n == name) {
+                    undefined.push(name.to_owned());
+                }
+            }
+        }
+        undefined
+    }
+
+    /// Graph of the grammar in Graphviz DOT language, where nodes are non-terminals, and edges
+    /// point from each rule to non-terminals it references, e.g. to render with `dot -Tsvg`.
+    ///
+    /// Nodes and edges are listed in order of their first appearance, without duplicates.
+    pub fn to_dot(&self) -> String {
+        let mut nodes: Vec<&str> = vec![];
+        let mut edges: Vec<(&str, &str)> = vec![];
+        for rule in self.rules.iter() {
+            if !nodes.contains(&rule.name) {
+                nodes.push(rule.name);
+            }
+            for symbol in rule.expression.iter().flat_map(|prod| prod.iter()) {
+                if let NonTerminal(name) = *symbol {
+                    if !edges.contains(&(rule.name, name)) {
+                        edges.push((rule.name, name));
+                    }
+                }
+            }
+        }
+        for &(_, name) in edges.iter() {
+            if !nodes.contains(&name) {
+                nodes.push(name);
+            }
+        }
+
+        // names may contain quotes, e.g. `<a"b>`
+        let quote = |name: &str| {
+            format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+        };
+        let mut dot = String::from("digraph grammar {\n");
+        for name in nodes {
+            dot += &format!("    {};\n", quote(name));
+        }
+        for (from, to) in edges {
+            dot += &format!("    {} -> {};\n", quote(from), quote(to));
+        }
+        dot += "}\n";
+        dot
+    }
+
+    /// FIRST sets of all non-terminals at once, computed by fixpoint iteration.
+    ///
+    /// Unlike `first`, it handles any recursion, and tolerates undefined non-terminals, whose
+    /// FIRST sets are taken as empty. As elsewhere, epsilon is an empty string.
+    pub fn first_sets(&self) -> HashMap<String, HashSet<String>> {
+        let mut sets: HashMap<String, HashSet<String>> = self
+            .rules
+            .iter()
+            .map(|rule| (rule.name.to_owned(), hash_set!()))
+            .collect();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for rule in self.rules.iter() {
+                for prod in rule.expression.iter() {
+                    let mut first: HashSet<String> = hash_set!();
+                    // whether all symbols so far may vanish
+                    let mut vanishes = true;
+                    for symbol in prod.iter() {
+                        match *symbol {
+                            Terminal(_) if symbol.is_epsilon() => continue,
+                            Terminal(t) => {
+                                first.insert(t.to_owned());
+                            }
+                            NonTerminal(name) => {
+                                if let Some(set) = sets.get(name) {
+                                    first.extend(set.iter().filter(|t| !t.is_empty()).cloned());
+                                    if set.contains("") {
+                                        continue;
+                                    }
+                                }
+                            }
+                        }
+                        vanishes = false;
+                        break;
+                    }
+                    if vanishes {
+                        first.insert(String::new());
+                    }
+
+                    let set = sets.get_mut(rule.name).unwrap();
+                    let before = set.len();
+                    set.extend(first);
+                    changed |= set.len() != before;
+                }
+            }
+        }
+        sets
+    }
+
     pub fn get_rule(&self, name: &str) -> Option<&GrammarRule<'a, 'b>> {
         self.rules.iter().find(|r| r.name == name)
     }
@@ -470,6 +771,141 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_parse() {
+        let source = r#"
+            <A> ::= <B> | "c" <D> ;
+            <B> ::= "b"
+                  | "" ;
+            <D> ::= "d"
+        "#;
+        let grammar = parse(source, FILENAME.into()).unwrap();
+
+        let names: Vec<_> = grammar.rules.iter().map(|rule| rule.name).collect();
+        assert_eq!(names, ["A", "B", "D"]);
+        assert_eq!(
+            grammar.rules[0].expression,
+            vec![vec![NonTerminal("B")], vec![Terminal("c"), NonTerminal("D")]]
+        );
+        assert_eq!(
+            grammar.rules[1].expression,
+            vec![vec![Terminal("b")], vec![GrammarSymbol::epsilon()]]
+        );
+        assert_eq!(grammar.rules[2].expression, vec![vec![Terminal("d")]]);
+
+        let error = parse("<A> ::= \"a\" ; \"b\" ::= <A>", FILENAME.into()).unwrap_err();
+        assert_eq!(error.span.range(), 14..17);
+        assert_eq!(error.description.unwrap(), "Expected non-terminal.");
+
+        let error = parse("<A> <B>", FILENAME.into()).unwrap_err();
+        assert_eq!(error.description.unwrap(), "Expected ::=.");
+        assert!(parse("<A> ::= @", FILENAME.into()).is_err());
+    }
+
+    #[test]
+    fn test_left_recursive() {
+        let grammar = parse(r#"<A> ::= <A> "x" | "y" ;"#, FILENAME.into()).unwrap();
+        assert_eq!(grammar.left_recursive(), ["A"]);
+
+        let source = r#"
+            <S> ::= <A> "s" ;
+            <A> ::= <B> "a" | "a" ;
+            <B> ::= <A> "b" ;
+        "#;
+        let grammar = parse(source, FILENAME.into()).unwrap();
+        assert_eq!(grammar.left_recursive(), ["A", "B"]);
+
+        // hidden behind a vanishing symbol
+        let source = r#"
+            <A> ::= <E> <A> "x" | "y" ;
+            <E> ::= "" ;
+            <C> ::= "c" <C> | "c" ;
+        "#;
+        let grammar = parse(source, FILENAME.into()).unwrap();
+        assert_eq!(grammar.left_recursive(), ["A"]);
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let source = r#"
+            <S> ::= <A> "s" ;
+            <A> ::= "a" | <A> <S> ;
+            <Orphan> ::= <B> ;
+            <B> ::= "b" ;
+        "#;
+        let grammar = parse(source, FILENAME.into()).unwrap();
+
+        assert_eq!(grammar.unreachable("S"), ["Orphan", "B"]);
+        assert_eq!(grammar.unreachable("Orphan"), ["S", "A"]);
+        assert!(grammar.undefined().is_empty());
+    }
+
+    #[test]
+    fn test_undefined() {
+        let source = r#"
+            <S> ::= <A> <Missing> | <Missing> ;
+            <A> ::= "a" <Other> ;
+        "#;
+        let grammar = parse(source, FILENAME.into()).unwrap();
+
+        assert_eq!(grammar.undefined(), ["Missing", "Other"]);
+        assert!(grammar.unreachable("S").is_empty());
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let source = r#"
+            <A> ::= <B> | "c" <D> ;
+            <B> ::= "b" <B> | <D> <Missing> ;
+            <D> ::= "d" ;
+            <A> ::= <B> ;
+        "#;
+        let dot = parse(source, FILENAME.into()).unwrap().to_dot();
+
+        assert_eq!(
+            dot,
+            concat!(
+                "digraph grammar {\n",
+                "    \"A\";\n",
+                "    \"B\";\n",
+                "    \"D\";\n",
+                "    \"Missing\";\n",
+                "    \"A\" -> \"B\";\n",
+                "    \"A\" -> \"D\";\n",
+                "    \"B\" -> \"B\";\n",
+                "    \"B\" -> \"D\";\n",
+                "    \"B\" -> \"Missing\";\n",
+                "}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_first_sets() {
+        let source = r#"
+            <E>  ::= <T> <E'> ;
+            <E'> ::= "+" <T> <E'> | "" ;
+            <T>  ::= <F> <T'> ;
+            <T'> ::= "*" <F> <T'> | "" ;
+            <F>  ::= "(" <E> ")" | "id" ;
+        "#;
+        let sets = parse(source, FILENAME.into()).unwrap().first_sets();
+        let first = |name: &str| {
+            let mut set: Vec<_> = sets[name].iter().map(String::as_str).collect();
+            set.sort();
+            set
+        };
+
+        assert_eq!(first("E"), ["(", "id"]);
+        assert_eq!(first("E'"), ["", "+"]);
+        assert_eq!(first("T'"), ["", "*"]);
+
+        // left recursion is fine too
+        let source = r#"<E> ::= <E> "+" <F> | <F> ; <F> ::= "x" | "" ;"#;
+        let sets = parse(source, FILENAME.into()).unwrap().first_sets();
+        assert_eq!(sets["E"], hash_set!["x".to_owned(), "+".to_owned(), "".to_owned()]);
+    }
+
     #[test]
     fn test_first_set() {
         let source = r#"
@@ -543,4 +979,35 @@ mod test {
             hash_set!["+", "$", ")"]
         );
     }
+
+    #[test]
+    fn test_first_set_empty() {
+        let source = r#"
+            <S> ::= <E> "x"
+            <E> ::= ""
+        "#;
+        let grammar = Grammar::from_str(source, FILENAME.into()).unwrap();
+        // empty sequence vanishes
+        assert_eq!(grammar.first([]).unwrap(), hash_set![""]);
+        // epsilon of a nullable prefix does not leak
+        assert_eq!(
+            grammar.first([NonTerminal("E"), Terminal("x")]).unwrap(),
+            hash_set!["x"]
+        );
+        assert_eq!(grammar.first([NonTerminal("S")]).unwrap(), hash_set!["x"]);
+        assert_eq!(grammar.first([NonTerminal("E")]).unwrap(), hash_set![""]);
+    }
+
+    #[test]
+    fn test_follow_set_cycle() {
+        // FOLLOW(A) and FOLLOW(B) depend on each other through vanishing tails
+        let source = r#"
+            <S> ::= <A> "s"
+            <A> ::= "a" <B> | ""
+            <B> ::= "b" <A> | ""
+        "#;
+        let grammar = Grammar::from_str(source, FILENAME.into()).unwrap();
+        assert_eq!(grammar.follow(NonTerminal("A"), NonTerminal("S")), hash_set!["s"]);
+        assert_eq!(grammar.follow(NonTerminal("B"), NonTerminal("S")), hash_set!["s"]);
+    }
 }
@@ -554,6 +554,20 @@ mod impls {
                         Operator(Def) => {
                             Err(self.error_expected("anything but ::= operator"))?;
                         }
+                        Operator(Range) => {
+                            Err(self.error_expected("anything but .. operator"))?;
+                        }
+                        Operator(Except) => {
+                            Err(self.error_expected("anything but - operator"))?;
+                        }
+                        // explicit concatenation is the same as juxtaposition
+                        Operator(Concat) => {}
+                        RepeatCount(_) => {
+                            Err(self.error_expected("anything but repetition count"))?;
+                        }
+                        Special(_) => {
+                            Err(self.error_expected("anything but special sequence"))?;
+                        }
                         Comment(_) => {}
                     },
                     None => {
@@ -978,6 +992,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_concat() {
+        let explicit = r#" <A> ::= <B> , "C" "#;
+        let rule = Parser::new(explicit, FILENAME.into()).parse_rule().unwrap().unwrap();
+        let implicit = r#" <A> ::= <B> "C" "#;
+        let expected = Parser::new(implicit, FILENAME.into()).parse_rule().unwrap().unwrap();
+        assert_eq!(rule.definitions.0, expected.definitions.0);
+    }
+
     #[test]
     fn test_parse_alternatives() {
         let source = r#" <A> ::= <B> | "#;
@@ -448,7 +448,7 @@ mod test {
 
         // println!("{}", TreeFmt(&tree));
 
-        assert!(expected.eq(&tree));
+        assert!(IdTreeEqExt::eq(&expected, &tree));
     }
 
     #[test]
@@ -530,7 +530,8 @@ mod test {
             .add(r"\)", constant!(Tok::Right))
             .add(r"\+", constant!(Tok::Plus))
             .add(r"a", constant!(Tok::A))
-            .build();
+            .build()
+            .unwrap();
 
         let mut ebnf = ebnf::Parser::new(grammar, "wiki/LL_parser".into())
             .parse()
@@ -69,16 +69,16 @@ mod tests {
         let left: Tree<()> = tree!();
         let right = tree!();
 
-        assert!(left.eq(&right));
+        assert!(IdTreeEqExt::eq(&left, &right));
     }
 
     #[test]
     fn test_one_empty_tree() {
-        let left = tree!();
+        let left: Tree<i32> = tree!();
         let right = tree!(1);
 
-        assert!(left.ne(&right));
-        assert!(right.ne(&left));
+        assert!(IdTreeEqExt::ne(&left, &right));
+        assert!(IdTreeEqExt::ne(&right, &left));
     }
 
     #[test]
@@ -86,7 +86,7 @@ mod tests {
         let left = tree!(1);
         let right = tree!(1);
 
-        assert!(left.eq(&right));
+        assert!(IdTreeEqExt::eq(&left, &right));
     }
 
     #[test]
@@ -94,7 +94,7 @@ mod tests {
         let left = tree!(42);
         let right = tree!(37);
 
-        assert!(left.ne(&right));
+        assert!(IdTreeEqExt::ne(&left, &right));
     }
 
     #[test]
@@ -102,7 +102,7 @@ mod tests {
         let left = tree!(1 => {2 => {3, 4}, 5 => {6,}, 7 => {}});
         let right = tree!(1 => {2 => {3, 4}, 5 => {6,}, 7 => {}});
 
-        assert!(left.eq(&right));
+        assert!(IdTreeEqExt::eq(&left, &right));
     }
 
     #[test]
@@ -110,7 +110,7 @@ mod tests {
         let left = tree!(1 => {2 => {3, 4}, 5 => {6,}, 7 => {}});
         let right = tree!(1 => {2 => {3}, 4 => {5, 6, 7}});
 
-        assert!(left.ne(&right));
+        assert!(IdTreeEqExt::ne(&left, &right));
     }
 
     #[test]
@@ -118,7 +118,7 @@ mod tests {
         let left = tree!(1 => {2, 3 => {4}});
         let right = tree!(1 => {4, 2 => {3}});
 
-        assert!(left.ne(&right));
+        assert!(IdTreeEqExt::ne(&left, &right));
     }
 
     #[test]
@@ -126,6 +126,6 @@ mod tests {
         let left: Tree<String> = tree!("one".to_string() => {"two".to_string()});
         let right: Tree<&str> = tree!("tree" => {"four" => {"five"}});
 
-        assert!(left.ne(&right));
+        assert!(IdTreeEqExt::ne(&left, &right));
     }
 }